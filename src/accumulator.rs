@@ -0,0 +1,166 @@
+use crate::aov::{Aov, AovBuffers, AovPixel};
+use crate::framebuffer::FrameBuffer;
+use crate::passes::{Pass, PassBuffers};
+use crate::render::ShadedPixel;
+use crate::tonemap::luminance;
+use crate::Vec3;
+
+//running sums of what pixel_shader returns, so that the image can be refined pass after pass and
+//saved at any time
+pub struct Accumulator {
+    pub width: u32,
+    pub height: u32,
+    pub color: Vec<Vec3>,
+    pub passes: Vec<Vec3>,   //n_passes colors per pixel
+    pub aovs: Vec<AovPixel>, //empty when no aov is needed
+    pub samples: Vec<u32>,
+    pub luminance_sq: Vec<f64>, //sums of the squared luminance of the samples
+    pub splats: Vec<Vec3>,      //light traced to the pixel, by the samples of every pixel
+    pub n_passes: usize,
+}
+
+//samples go to the pixels whose estimated error is still above the threshold, once they all
+//have min_spp
+pub struct AdaptiveSampling {
+    pub threshold: f64,
+    pub min_spp: u32,
+}
+
+impl Accumulator {
+    pub fn new(width: u32, height: u32, n_passes: usize, keep_aovs: bool) -> Accumulator {
+        let n_pixels = (width * height) as usize;
+        Accumulator {
+            width,
+            height,
+            color: vec![Vec3::ZERO; n_pixels],
+            passes: vec![Vec3::ZERO; n_pixels * n_passes],
+            aovs: if keep_aovs {
+                (0..n_pixels).map(|_| AovPixel::default()).collect()
+            } else {
+                vec![]
+            },
+            samples: vec![0; n_pixels],
+            luminance_sq: vec![0.0; n_pixels],
+            splats: vec![Vec3::ZERO; n_pixels],
+            n_passes,
+        }
+    }
+
+    //adds the average of spp samples
+    pub fn add(&mut self, i: u32, j: u32, shaded: &ShadedPixel, spp: u32) {
+        let p = (j * self.width + i) as usize;
+        let weight = spp as f64;
+        self.color[p] = &self.color[p] + &shaded.color.scale(weight);
+        for (k, pass) in shaded.passes.iter().enumerate() {
+            let q = p * self.n_passes + k;
+            self.passes[q] = &self.passes[q] + &pass.scale(weight);
+        }
+        if !self.aovs.is_empty() {
+            self.aovs[p].accumulate(&shaded.aov, self.samples[p] == 0);
+        }
+        self.luminance_sq[p] += shaded.luminance_sq * weight;
+        self.samples[p] += spp;
+        for splat in &shaded.splats {
+            let q = (splat.y * self.width + splat.x) as usize;
+            self.splats[q] = &self.splats[q] + &splat.color;
+        }
+    }
+
+    fn inv_samples(&self, p: usize) -> f64 {
+        1.0 / (self.samples[p].max(1) as f64)
+    }
+
+    //standard error of the mean luminance, relative to the square root of the luminance so that
+    //dark pixels need about as many samples as bright ones to look converged
+    pub fn error(&self, p: usize) -> f64 {
+        let n = self.samples[p] as f64;
+        if n < 2.0 {
+            return f64::INFINITY;
+        }
+        let mean = luminance(&self.color[p]) / n;
+        let variance = (self.luminance_sq[p] / n - mean * mean).max(0.0) * n / (n - 1.0);
+        (variance / n).sqrt() / mean.max(1e-4).sqrt()
+    }
+
+    //how many samples each pixel gets in the next pass, 0 for those that are done. A pixel is
+    //only converged when its neighbours are, as the error of a single pixel is itself noisy
+    pub fn next_pass(
+        &self,
+        max_spp: u32,
+        pass_spp: u32,
+        adaptive: Option<&AdaptiveSampling>,
+    ) -> Vec<u32> {
+        let (width, height) = (self.width as i64, self.height as i64);
+        let errors: Vec<f64> = match adaptive {
+            Some(_) => (0..self.samples.len()).map(|p| self.error(p)).collect(),
+            None => vec![],
+        };
+        (0..self.samples.len())
+            .map(|p| {
+                let remaining = max_spp.saturating_sub(self.samples[p]);
+                let converged = adaptive.is_some_and(|adaptive| {
+                    let (i, j) = (p as i64 % width, p as i64 / width);
+                    self.samples[p] >= adaptive.min_spp
+                        && (j - 1..=j + 1)
+                            .flat_map(|nj| (i - 1..=i + 1).map(move |ni| (ni, nj)))
+                            .filter(|(ni, nj)| *ni >= 0 && *ni < width && *nj >= 0 && *nj < height)
+                            .all(|(ni, nj)| errors[(nj * width + ni) as usize] < adaptive.threshold)
+                });
+                if converged {
+                    0
+                } else {
+                    pass_spp.min(remaining)
+                }
+            })
+            .collect()
+    }
+
+    //the current estimate. Every sample may splat to any pixel, so splats are averaged over the
+    //samples of the whole image
+    pub fn image(&self) -> FrameBuffer {
+        let total_samples: u64 = self.samples.iter().map(|s| *s as u64).sum();
+        let splat_scale = self.samples.len() as f64 / total_samples.max(1) as f64;
+        FrameBuffer {
+            width: self.width,
+            height: self.height,
+            pixels: self
+                .color
+                .iter()
+                .zip(self.splats.iter())
+                .enumerate()
+                .map(|(p, (c, splat))| &c.scale(self.inv_samples(p)) + &splat.scale(splat_scale))
+                .collect(),
+        }
+    }
+
+    pub fn pass_buffers(&self, passes: &[Pass]) -> PassBuffers {
+        let mut buffers = PassBuffers::new(passes, self.width, self.height);
+        for j in 0..self.height {
+            for i in 0..self.width {
+                let p = (j * self.width + i) as usize;
+                let colors: Vec<Vec3> = self.passes[p * self.n_passes..(p + 1) * self.n_passes]
+                    .iter()
+                    .map(|c| c.scale(self.inv_samples(p)))
+                    .collect();
+                buffers.put_pixel(i, j, &colors);
+            }
+        }
+        buffers
+    }
+
+    pub fn aov_buffers(&self, aovs: &[Aov]) -> AovBuffers {
+        let mut buffers = AovBuffers::new(aovs, self.width, self.height);
+        if self.aovs.is_empty() {
+            return buffers;
+        }
+        for j in 0..self.height {
+            for i in 0..self.width {
+                let p = (j * self.width + i) as usize;
+                let mut pixel = self.aovs[p].scaled(1.0 / self.aovs[p].hits.max(1.0));
+                pixel.samples = self.samples[p] as f64;
+                buffers.put_pixel(i, j, &pixel);
+            }
+        }
+        buffers
+    }
+}
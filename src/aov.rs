@@ -0,0 +1,241 @@
+use std::io;
+
+use crate::framebuffer::{extension, sibling_output, FrameBuffer, Output};
+use crate::tonemap::ToneMap;
+use crate::Vec3;
+
+//arbitrary output variables, describing the first surface seen through each pixel
+#[derive(Clone, Copy, PartialEq)]
+pub enum Aov {
+    Depth,      //camera space depth, along the optical axis
+    Normal,     //world space normal, facing the camera
+    Albedo,     //reflectance of the material
+    Position,   //world space position
+    ObjectId,   //index into Scene::objects, -1 for the background
+    MaterialId, //index of the material among those of the scene, -1 for the background
+    Samples,    //number of samples taken, a heatmap of adaptive sampling
+}
+
+impl Aov {
+    pub const ALL: [Aov; 7] = [
+        Aov::Depth,
+        Aov::Normal,
+        Aov::Albedo,
+        Aov::Position,
+        Aov::ObjectId,
+        Aov::MaterialId,
+        Aov::Samples,
+    ];
+
+    pub fn from_name(name: &str) -> Option<Aov> {
+        Aov::ALL.iter().cloned().find(|aov| aov.name() == name)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Aov::Depth => "depth",
+            Aov::Normal => "normal",
+            Aov::Albedo => "albedo",
+            Aov::Position => "position",
+            Aov::ObjectId => "object_id",
+            Aov::MaterialId => "material_id",
+            Aov::Samples => "samples",
+        }
+    }
+
+    //names of the channels in an exr layer
+    fn channels(&self) -> &'static [&'static str] {
+        match self {
+            Aov::Depth => &["Z"],
+            Aov::Normal | Aov::Position => &["X", "Y", "Z"],
+            Aov::Albedo => &["R", "G", "B"],
+            Aov::ObjectId | Aov::MaterialId => &["id"],
+            Aov::Samples => &["count"],
+        }
+    }
+}
+
+//what pixel_shader found at the first hit, averaged over the samples of a pixel that hit something
+//(ids come from the first sample)
+pub struct AovPixel {
+    pub depth: f64,
+    pub normal: Vec3,
+    pub albedo: Vec3,
+    pub position: Vec3,
+    pub object_id: f64,
+    pub material_id: f64,
+    pub samples: f64, //set by the accumulator
+    pub hits: f64,    //samples the continuous values are averaged over
+}
+
+impl Default for AovPixel {
+    fn default() -> AovPixel {
+        AovPixel {
+            depth: 0.0,
+            normal: Vec3::ZERO,
+            albedo: Vec3::ZERO,
+            position: Vec3::ZERO,
+            object_id: -1.0,
+            material_id: -1.0,
+            samples: 0.0,
+            hits: 0.0,
+        }
+    }
+}
+
+impl AovPixel {
+    //adds the continuous values of other weighted by its hits, keeping the ids of the first pixel
+    //added
+    pub fn accumulate(&mut self, other: &AovPixel, is_first: bool) {
+        let weight = other.hits;
+        self.hits += weight;
+        self.depth += other.depth * weight;
+        self.normal = &self.normal + &other.normal.scale(weight);
+        self.albedo = &self.albedo + &other.albedo.scale(weight);
+        self.position = &self.position + &other.position.scale(weight);
+        if is_first {
+            self.object_id = other.object_id;
+            self.material_id = other.material_id;
+        }
+    }
+
+    pub fn scaled(&self, scale: f64) -> AovPixel {
+        AovPixel {
+            depth: self.depth * scale,
+            normal: self.normal.scale(scale),
+            albedo: self.albedo.scale(scale),
+            position: self.position.scale(scale),
+            object_id: self.object_id,
+            material_id: self.material_id,
+            samples: self.samples,
+            hits: self.hits,
+        }
+    }
+
+    fn get(&self, aov: Aov) -> Vec3 {
+        let scalar = |v: f64| Vec3 { x: v, y: v, z: v };
+        match aov {
+            Aov::Depth => scalar(self.depth),
+            Aov::Normal => self.normal.clone(),
+            Aov::Albedo => self.albedo.clone(),
+            Aov::Position => self.position.clone(),
+            Aov::ObjectId => scalar(self.object_id),
+            Aov::MaterialId => scalar(self.material_id),
+            Aov::Samples => scalar(self.samples),
+        }
+    }
+}
+
+//one frame buffer per requested aov, scalar ones being stored in every channel
+pub struct AovBuffers {
+    pub buffers: Vec<(Aov, FrameBuffer)>,
+}
+
+//a color that is easy to tell apart from its neighbours, for ids
+fn id_color(id: f64) -> Vec3 {
+    if id < 0.0 {
+        return Vec3::ZERO;
+    }
+    let hash = (id as u64 + 1).wrapping_mul(0x9E3779B97F4A7C15);
+    let channel = |shift: u32| ((hash >> shift) & 0xFF) as f64 / 255.0;
+    Vec3 {
+        x: channel(8),
+        y: channel(24),
+        z: channel(40),
+    }
+}
+
+//from blue for 0 to green then red for 1
+pub fn heat_color(t: f64) -> Vec3 {
+    Vec3 {
+        x: t,
+        y: 1.0 - (2.0 * t - 1.0).abs(),
+        z: 1.0 - t,
+    }
+}
+
+impl AovBuffers {
+    pub fn new(aovs: &[Aov], width: u32, height: u32) -> AovBuffers {
+        AovBuffers {
+            buffers: aovs
+                .iter()
+                .map(|aov| (*aov, FrameBuffer::new(width, height)))
+                .collect(),
+        }
+    }
+
+    pub fn put_pixel(&mut self, i: u32, j: u32, pixel: &AovPixel) {
+        for (aov, buffer) in self.buffers.iter_mut() {
+            buffer.put_pixel(i, j, pixel.get(*aov));
+        }
+    }
+
+    //values remapped to 0 to 1 for 8 bit images
+    fn visualize(aov: Aov, buffer: &FrameBuffer) -> FrameBuffer {
+        let pixels = &buffer.pixels;
+        let remapped: Vec<Vec3> = match aov {
+            Aov::Depth => {
+                let max = pixels.iter().map(|p| p.x).fold(0.0, f64::max);
+                pixels
+                    .iter()
+                    .map(|p| p.scale(1.0 / max.max(1e-9)))
+                    .collect()
+            }
+            Aov::Normal => pixels
+                .iter()
+                .map(|p| p.affine(-1.0, 1.0, 0.0, 1.0))
+                .collect(),
+            Aov::Albedo => pixels.clone(),
+            Aov::Position => {
+                let min = pixels
+                    .iter()
+                    .fold(f64::INFINITY, |m, p| m.min(p.x.min(p.y).min(p.z)));
+                let max = pixels
+                    .iter()
+                    .fold(f64::NEG_INFINITY, |m, p| m.max(p.x.max(p.y).max(p.z)));
+                pixels
+                    .iter()
+                    .map(|p| p.affine(min, max.max(min + 1e-9), 0.0, 1.0))
+                    .collect()
+            }
+            Aov::ObjectId | Aov::MaterialId => pixels.iter().map(|p| id_color(p.x)).collect(),
+            Aov::Samples => {
+                //from blue for the fewest samples to red for the most
+                let max = pixels.iter().map(|p| p.x).fold(1.0, f64::max);
+                pixels.iter().map(|p| heat_color(p.x / max)).collect()
+            }
+        };
+        FrameBuffer {
+            width: buffer.width,
+            height: buffer.height,
+            pixels: remapped,
+        }
+    }
+
+    //raw values, as layers named after the aovs
+    pub fn exr_channels(&self) -> Vec<(String, Vec<f32>)> {
+        self.buffers
+            .iter()
+            .flat_map(|(aov, buffer)| buffer.exr_channels(aov.name(), aov.channels()))
+            .collect()
+    }
+
+    //one file per aov next to the render, like render_depth.png. Float formats get the raw
+    //values, others get them remapped to 0 to 1
+    pub fn save_files(&self, output: &Output) -> io::Result<()> {
+        let is_float = matches!(extension(&output.path).as_str(), "hdr" | "pfm");
+        for (aov, buffer) in &self.buffers {
+            let aov_output = Output {
+                tonemap: if is_float { None } else { Some(ToneMap::Clamp) },
+                exposure: 0.0,
+                ..sibling_output(output, aov.name())
+            };
+            if is_float {
+                buffer.save(&aov_output)?;
+            } else {
+                AovBuffers::visualize(*aov, buffer).save(&aov_output)?;
+            }
+        }
+        Ok(())
+    }
+}
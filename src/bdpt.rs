@@ -0,0 +1,482 @@
+use crate::bsdf::{bsdf, light_weight, roulette, scatter, scatter_pdf, spawn_ray, EPSILON};
+use crate::integrator::{Integrator, PathTracer, Splat};
+use crate::lights::{Emission, Lights};
+use crate::render::{intersect, Context, Intersection, Ray};
+use crate::sampler::Sampler;
+use crate::scene::Scene;
+use crate::vector::Vec3;
+use crate::Material;
+
+//bidirectional path tracing : a subpath from the camera and one from a light are connected in
+//every possible way, each connection being weighted by multiple importance sampling (the balance
+//heuristic) against the other ways the same path could have been sampled. Paths that end at the
+//camera itself (light tracing) are splatted to the pixel they land on.
+//The scattering is the path tracer's, written as densities so that subpaths can be joined
+pub struct Bdpt {
+    bounces: u8,
+    roulette_depth: u8,
+    lights: Lights,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Camera,
+    Light,
+    Surface,
+}
+
+struct Vertex<'a> {
+    kind: Kind,
+    pos: Vec3,
+    normal: Vec3, //unused for the camera
+    mat: &'a Material,
+    object: usize,
+    beta: Vec3,   //contribution of the subpath up to the vertex, over its density
+    dir_in: Vec3, //direction the subpath arrived along
+    delta: bool,  //left through a delta lobe
+    pdf_fwd: f64, //area densities of the vertex, sampled from its subpath or from the other one
+    pdf_rev: f64,
+}
+
+//area density at to of a direction of solid angle density pdf sampled at from
+fn to_area(pdf: f64, from: &Vec3, to: &Vertex) -> f64 {
+    let d = to.pos.minus(from);
+    let d2 = d.dot(&d);
+    let cos = match to.kind {
+        Kind::Camera => 1.0,
+        _ => to.normal.dot(&d).abs() / d2.sqrt(),
+    };
+    pdf * cos / d2
+}
+
+fn visible(ctx: &Context, a: &Vec3, b: &Vec3, time: f64) -> bool {
+    let d = b.minus(a);
+    let dist = d.norm();
+    let dir = d.scale(1.0 / dist);
+    let mut ray = Ray::new(a + &dir.scale(EPSILON), dir);
+    ray.time = time;
+    let int = intersect(&ray, &ctx.scene);
+    !int.hit || int.dist > dist - 2.0 * EPSILON
+}
+
+//screen coordinates (-1 to 1) of the direction dir from the camera, and the cosine to its axis
+fn project(ctx: &Context, dir: &Vec3) -> Option<(f64, f64, f64)> {
+    let camera = &ctx.camera;
+    let local = camera.rot.transposed().apply_dir3(dir).as_vec3();
+    if local.z >= 0.0 {
+        return None;
+    }
+    let x = (-camera.focal_length * local.x / local.z - camera.shift_x) / camera.aspect;
+    let y = -camera.focal_length * local.y / local.z - camera.shift_y;
+    if x.abs() > 1.0 || y.abs() > 1.0 {
+        return None;
+    }
+    Some((x, y, -local.z / local.norm()))
+}
+
+//the pinhole's window spans -aspect to aspect by -1 to 1, on a plane at focal_length
+fn film_area(ctx: &Context) -> f64 {
+    4.0 * ctx.camera.aspect
+}
+
+//density of the primary ray along dir, the film being sampled uniformly. It is also the
+//importance the camera gives to that direction
+fn camera_pdf(ctx: &Context, dir: &Vec3) -> f64 {
+    match project(ctx, dir) {
+        Some((_, _, cos)) => ctx.camera.focal_length.powi(2) / (film_area(ctx) * cos.powi(3)),
+        None => 0.0,
+    }
+}
+
+impl Bdpt {
+    pub fn new(scene: &Scene, path_tracer: &PathTracer) -> Bdpt {
+        Bdpt {
+            bounces: path_tracer.bounces,
+            roulette_depth: path_tracer.roulette_depth,
+            lights: Lights::new(scene),
+        }
+    }
+
+    //extends path by following ray, sampling the bounces from the given dimension on
+    #[allow(clippy::too_many_arguments)]
+    fn random_walk<'a>(
+        &self,
+        ctx: &'a Context,
+        mut ray: Ray,
+        mut beta: Vec3,
+        from_light: bool,
+        sampler: &mut Sampler,
+        first_bounce: u32,
+        max_vertices: usize,
+        path: &mut Vec<Vertex<'a>>,
+    ) {
+        let mut throughput = Vec3::ONE;
+        let mut depth = 0;
+        while path.len() < max_vertices {
+            let int = intersect(&ray, &ctx.scene);
+            if !int.hit {
+                break;
+            }
+            let mut vertex = Vertex {
+                kind: Kind::Surface,
+                pos: int.pos,
+                normal: int.normal,
+                mat: int.mat,
+                object: int.object,
+                beta: beta.clone(),
+                dir_in: ray.dir.clone(),
+                delta: false,
+                pdf_fwd: 0.0,
+                pdf_rev: 0.0,
+            };
+
+            sampler.start_bounce(first_bounce + 2 * depth);
+            let u_lobe = sampler.get_1d();
+            let u_refraction = sampler.get_1d();
+            let u_direction = sampler.get_2d();
+            let u_roulette = sampler.get_1d();
+            let scattered = scatter(
+                vertex.mat,
+                &vertex.normal,
+                &ray,
+                u_lobe,
+                u_refraction,
+                u_direction,
+            );
+            let dir = &scattered.dir;
+            vertex.delta = scattered.delta;
+            let weight = if from_light {
+                light_weight(vertex.mat, &vertex.normal, &ray, &scattered)
+            } else {
+                scattered.weight.clone()
+            };
+
+            let (pos, normal) = (vertex.pos.clone(), vertex.normal.clone());
+            path.push(vertex);
+            if weight.norm() == 0.0 || !weight.norm().is_finite() || !dir.x.is_finite() {
+                break;
+            }
+            beta = beta.mult(&weight);
+            throughput = throughput.mult(&weight);
+
+            depth += 1;
+            if depth > self.roulette_depth as u32 {
+                let Some(survival) = roulette(&throughput, u_roulette) else {
+                    break;
+                };
+                beta = beta.scale(1.0 / survival);
+                throughput = throughput.scale(1.0 / survival);
+            }
+
+            ray = spawn_ray(&pos, &normal, scattered.dir, ray.time);
+            ray.n = scattered.next_n;
+        }
+    }
+
+    //the area densities of the vertices of path, from their positions alone : the directions that
+    //were sampled leave from offset origins, and the connections must agree with the subpaths on
+    //the density of every path for the weights to sum to 1
+    fn set_densities(&self, ctx: &Context, path: &mut [Vertex]) {
+        for k in 1..path.len() {
+            path[k].pdf_fwd = if path[k - 1].delta {
+                0.0
+            } else {
+                self.pdf(
+                    ctx,
+                    &path[k - 1],
+                    k.checked_sub(2).map(|i| &path[i]),
+                    &path[k],
+                )
+            };
+        }
+        for k in 0..path.len().saturating_sub(2) {
+            path[k].pdf_rev = if path[k + 1].delta {
+                0.0
+            } else {
+                self.pdf(ctx, &path[k + 1], Some(&path[k + 2]), &path[k])
+            };
+        }
+    }
+
+    fn light_subpath<'a>(
+        &self,
+        ctx: &'a Context,
+        time: f64,
+        sampler: &mut Sampler,
+        u_light: f64,
+    ) -> Vec<Vertex<'a>> {
+        let mut path = vec![];
+        sampler.start_bounce(1);
+        let (u_pos, u_dir) = (sampler.get_2d(), sampler.get_2d());
+        let Some(emission) = self.lights.sample(&ctx.scene, u_light, u_pos, u_dir) else {
+            return path;
+        };
+        let Emission {
+            object,
+            pos,
+            normal,
+            dir,
+            pdf_pos,
+            pdf_dir,
+        } = emission;
+        let mat = ctx.scene.objects[object].material();
+        path.push(Vertex {
+            kind: Kind::Light,
+            pos: pos.clone(),
+            normal: normal.clone(),
+            mat,
+            object,
+            beta: mat.emissive.scale(1.0 / pdf_pos),
+            dir_in: Vec3::ZERO,
+            delta: false,
+            pdf_fwd: pdf_pos,
+            pdf_rev: 0.0,
+        });
+        if pdf_dir == 0.0 {
+            return path;
+        }
+        let beta = mat
+            .emissive
+            .scale(normal.dot(&dir).abs() / (pdf_pos * pdf_dir));
+        let ray = spawn_ray(&pos, &normal, dir, time);
+        self.random_walk(
+            ctx,
+            ray,
+            beta,
+            true,
+            sampler,
+            3,
+            self.bounces as usize + 1,
+            &mut path,
+        );
+        self.set_densities(ctx, &mut path);
+        path
+    }
+
+    //area density of sampling next from v, v having been reached from prev
+    fn pdf(&self, ctx: &Context, v: &Vertex, prev: Option<&Vertex>, next: &Vertex) -> f64 {
+        let dir = next.pos.minus(&v.pos).normalized();
+        let pdf_dir = match v.kind {
+            Kind::Camera => camera_pdf(ctx, &dir),
+            Kind::Light => Lights::pdf_dir(&v.normal, &dir),
+            Kind::Surface => {
+                let dir_in = v.pos.minus(&prev.unwrap().pos).normalized();
+                scatter_pdf(v.mat, &v.normal, &dir_in, &dir)
+            }
+        };
+        to_area(pdf_dir, &v.pos, next)
+    }
+
+    //area density of next for a light subpath starting at v
+    fn emission_pdf(&self, v: &Vertex, next: &Vertex) -> f64 {
+        let dir = next.pos.minus(&v.pos).normalized();
+        to_area(Lights::pdf_dir(&v.normal, &dir), &v.pos, next)
+    }
+
+    //balance heuristic of the path made of s light and t camera vertices
+    fn mis_weight(
+        &self,
+        ctx: &Context,
+        camera: &[Vertex],
+        light: &[Vertex],
+        s: usize,
+        t: usize,
+    ) -> f64 {
+        if s + t == 2 {
+            return 1.0;
+        }
+        let pt = &camera[t - 1];
+        let pt_minus = if t > 1 { Some(&camera[t - 2]) } else { None };
+        let qs = if s > 0 { Some(&light[s - 1]) } else { None };
+        let qs_minus = if s > 1 { Some(&light[s - 2]) } else { None };
+
+        //the densities of the vertices around the connection, had they been sampled by the
+        //other subpath
+        let pt_rev = match qs {
+            Some(qs) => self.pdf(ctx, qs, qs_minus, pt),
+            None => self.lights.pdf_pos(pt.object),
+        };
+        if s == 0 && pt_rev == 0.0 {
+            return 1.0; //a light that can't be sampled, only the camera subpath can hit it
+        }
+        let pt_minus_rev = match (pt_minus, qs) {
+            (Some(pt_minus), Some(qs)) => self.pdf(ctx, pt, Some(qs), pt_minus),
+            (Some(pt_minus), None) => self.emission_pdf(pt, pt_minus),
+            _ => 0.0,
+        };
+        let qs_rev = qs.map_or(0.0, |qs| self.pdf(ctx, pt, pt_minus, qs));
+        let qs_minus_rev = match (qs, qs_minus) {
+            (Some(qs), Some(qs_minus)) => self.pdf(ctx, qs, Some(pt), qs_minus),
+            _ => 0.0,
+        };
+
+        //ratios of the densities of the other strategies to the one of this one, delta lobes
+        //having densities of 0 that cancel out
+        let remap = |pdf: f64| if pdf == 0.0 { 1.0 } else { pdf };
+        let mut sum = 0.0;
+        let mut ratio = 1.0;
+        for i in (1..t).rev() {
+            let pdf_rev = match t - 1 - i {
+                0 => pt_rev,
+                1 => pt_minus_rev,
+                _ => camera[i].pdf_rev,
+            };
+            ratio *= remap(pdf_rev) / remap(camera[i].pdf_fwd);
+            let delta = |k: usize| k < t - 1 && camera[k].delta;
+            if !delta(i) && !delta(i - 1) {
+                sum += ratio;
+            }
+        }
+        ratio = 1.0;
+        for i in (0..s).rev() {
+            let pdf_rev = match s - 1 - i {
+                0 => qs_rev,
+                1 => qs_minus_rev,
+                _ => light[i].pdf_rev,
+            };
+            ratio *= remap(pdf_rev) / remap(light[i].pdf_fwd);
+            let delta = |k: usize| k < s - 1 && light[k].delta;
+            if !delta(i) && (i == 0 || !delta(i - 1)) {
+                sum += ratio;
+            }
+        }
+        1.0 / (1.0 + sum)
+    }
+
+    fn connectable(v: &Vertex) -> bool {
+        match v.kind {
+            Kind::Surface => v.mat.roughness > 0.0 && v.mat.specularity < 1.0,
+            _ => true,
+        }
+    }
+
+    //the bsdf at v, of the path coming from the camera along dir_in (the light subpath arrived
+    //the other way) and going on to the light along dir_out
+    fn camera_bsdf(v: &Vertex, dir_in: &Vec3, dir_out: &Vec3) -> Vec3 {
+        match v.kind {
+            Kind::Surface => bsdf(v.mat, &v.normal, dir_in, dir_out),
+            _ => Vec3::ONE, //lights emit the same radiance everywhere
+        }
+    }
+}
+
+impl Integrator for Bdpt {
+    fn radiance<'a>(
+        &self,
+        ctx: &'a Context,
+        ray: Ray,
+        _hit: Intersection<'a>,
+        sampler: &mut Sampler,
+        _passes: &mut [Vec3],
+        splats: &mut Vec<Splat>,
+    ) -> Vec3 {
+        let (width, height) = (ctx.width as f64, ctx.height as f64);
+        //the primary ray is moved to a random point of its pixel, as light tracing needs pixels
+        //to have an area
+        let Some((x, y, _)) = project(ctx, &ray.dir) else {
+            return Vec3::ZERO;
+        };
+        let (i, j) = (
+            ((x + 1.0) / 2.0 * width).floor(),
+            ((1.0 - y) / 2.0 * height).floor(),
+        );
+        sampler.start_bounce(0);
+        let (u, v) = sampler.get_2d();
+        let u_light = sampler.get_1d();
+        let dir = ctx
+            .camera
+            .ray_dir(2.0 * (i + u) / width - 1.0, 1.0 - 2.0 * (j + v) / height);
+
+        let time = ray.time;
+        let mut camera = vec![Vertex {
+            kind: Kind::Camera,
+            pos: ray.origin.clone(),
+            normal: Vec3::ZERO,
+            mat: Material::default(),
+            object: 0,
+            beta: Vec3::ONE,
+            dir_in: Vec3::ZERO,
+            delta: false,
+            pdf_fwd: 1.0,
+            pdf_rev: 0.0,
+        }];
+        let mut primary = Ray::new(ray.origin.clone(), dir.clone());
+        primary.time = time;
+        self.random_walk(
+            ctx,
+            primary,
+            Vec3::ONE,
+            false,
+            sampler,
+            2,
+            self.bounces as usize + 2,
+            &mut camera,
+        );
+        self.set_densities(ctx, &mut camera);
+        let light = self.light_subpath(ctx, time, sampler, u_light);
+
+        let mut radiance = Vec3::ZERO;
+        for t in 1..=camera.len() {
+            for s in 0..=light.len() {
+                if (s == 1 && t == 1) || s + t < 2 || s + t - 2 > self.bounces as usize {
+                    continue;
+                }
+                let pt = &camera[t - 1];
+                if s == 0 {
+                    //the camera subpath hit a light
+                    if t < 2 || pt.mat.emissive.norm() == 0.0 {
+                        continue;
+                    }
+                    let contribution = pt.beta.mult(&pt.mat.emissive);
+                    radiance = &radiance
+                        + &contribution.scale(self.mis_weight(ctx, &camera, &light, s, t));
+                    continue;
+                }
+                let qs = &light[s - 1];
+                if !Self::connectable(pt) || !Self::connectable(qs) {
+                    continue;
+                }
+                let d = qs.pos.minus(&pt.pos);
+                let d2 = d.dot(&d);
+                let dir = d.scale(1.0 / d2.sqrt());
+                if t == 1 {
+                    //light tracing, to the pixel the vertex is seen in
+                    let Some((x, y, cos)) = project(ctx, &dir) else {
+                        continue;
+                    };
+                    let f = bsdf(qs.mat, &qs.normal, &dir, &qs.dir_in.scale(-1.0));
+                    let importance = camera_pdf(ctx, &dir);
+                    let contribution = qs
+                        .beta
+                        .mult(&f)
+                        .scale(importance * qs.normal.dot(&dir).abs() / d2);
+                    if contribution.norm() == 0.0 || !visible(ctx, &pt.pos, &qs.pos, time) {
+                        continue;
+                    }
+                    let weight = self.mis_weight(ctx, &camera, &light, s, t);
+                    splats.push(Splat {
+                        x: (((x + 1.0) / 2.0 * width) as u32).min(ctx.width - 1),
+                        y: (((1.0 - y) / 2.0 * height) as u32).min(ctx.height - 1),
+                        color: ctx.film.develop(&contribution.scale(weight), cos),
+                    });
+                    continue;
+                }
+                let f_camera = Self::camera_bsdf(pt, &pt.dir_in, &dir);
+                let f_light = Self::camera_bsdf(qs, &dir, &qs.dir_in.scale(-1.0));
+                let g = pt.normal.dot(&dir).abs() * qs.normal.dot(&dir).abs() / d2;
+                let contribution = pt
+                    .beta
+                    .mult(&f_camera)
+                    .mult(&f_light)
+                    .mult(&qs.beta)
+                    .scale(g);
+                if contribution.norm() == 0.0 || !visible(ctx, &pt.pos, &qs.pos, time) {
+                    continue;
+                }
+                let weight = self.mis_weight(ctx, &camera, &light, s, t);
+                radiance = &radiance + &contribution.scale(weight);
+            }
+        }
+        radiance
+    }
+}
@@ -0,0 +1,131 @@
+use crate::sampler::mix;
+
+//a tileable blue noise mask of size x size values in [0, 1), made with Ulichney's void and cluster
+//method : pixels are ranked by inserting them one at a time where they are the farthest from the
+//others, so that any threshold of the mask is evenly spread
+pub struct BlueNoiseMask {
+    pub size: usize,
+    pub values: Vec<f64>,
+}
+
+const SIGMA: f64 = 1.5;
+
+struct Energy {
+    size: usize,
+    kernel: Vec<f64>, //gaussian of the toroidal offset
+    energy: Vec<f64>, //sum of the kernel around every set pixel
+}
+
+impl Energy {
+    fn new(size: usize) -> Energy {
+        let mut kernel = vec![0.0; size * size];
+        for dy in 0..size {
+            for dx in 0..size {
+                let wrap = |d: usize| d.min(size - d) as f64;
+                let d2 = wrap(dx).powi(2) + wrap(dy).powi(2);
+                kernel[dy * size + dx] = (-d2 / (2.0 * SIGMA * SIGMA)).exp();
+            }
+        }
+        Energy {
+            size,
+            kernel,
+            energy: vec![0.0; size * size],
+        }
+    }
+
+    fn update(&mut self, p: usize, sign: f64) {
+        let size = self.size;
+        let (px, py) = (p % size, p / size);
+        for y in 0..size {
+            for x in 0..size {
+                let dx = (x + size - px) % size;
+                let dy = (y + size - py) % size;
+                self.energy[y * size + x] += sign * self.kernel[dy * size + dx];
+            }
+        }
+    }
+
+    //the set pixel with the most energy (in the tightest cluster) or the unset one with the least
+    //(in the largest void)
+    fn extreme(&self, pattern: &[bool], set: bool) -> usize {
+        let candidates = (0..pattern.len()).filter(|p| pattern[*p] == set);
+        if set {
+            candidates
+                .max_by(|a, b| self.energy[*a].total_cmp(&self.energy[*b]))
+                .unwrap()
+        } else {
+            candidates
+                .min_by(|a, b| self.energy[*a].total_cmp(&self.energy[*b]))
+                .unwrap()
+        }
+    }
+}
+
+impl BlueNoiseMask {
+    pub fn new(size: usize, seed: u64) -> BlueNoiseMask {
+        let n = size * size;
+        let mut pattern = vec![false; n];
+        let mut energy = Energy::new(size);
+
+        //a random initial pattern of a tenth of the pixels
+        let n_initial = (n / 10).max(1);
+        let mut k = 0;
+        while pattern.iter().filter(|p| **p).count() < n_initial {
+            let p = (mix(seed ^ mix(k)) % n as u64) as usize;
+            if !pattern[p] {
+                pattern[p] = true;
+                energy.update(p, 1.0);
+            }
+            k += 1;
+        }
+        //evened out by moving the pixel of the tightest cluster to the largest void, until that
+        //pixel is already in the largest void
+        loop {
+            let cluster = energy.extreme(&pattern, true);
+            pattern[cluster] = false;
+            energy.update(cluster, -1.0);
+            let void = energy.extreme(&pattern, false);
+            pattern[void] = true;
+            energy.update(void, 1.0);
+            if void == cluster {
+                break;
+            }
+        }
+
+        let mut ranks = vec![0; n];
+        //the initial pixels are ranked by removing them from the tightest clusters
+        let mut removal = pattern.clone();
+        let mut removal_energy = Energy {
+            size,
+            kernel: energy.kernel.clone(),
+            energy: energy.energy.clone(),
+        };
+        for rank in (0..n_initial).rev() {
+            let cluster = removal_energy.extreme(&removal, true);
+            removal[cluster] = false;
+            removal_energy.update(cluster, -1.0);
+            ranks[cluster] = rank;
+        }
+        //the others by filling the largest voids
+        for rank in n_initial..n {
+            let void = energy.extreme(&pattern, false);
+            pattern[void] = true;
+            energy.update(void, 1.0);
+            ranks[void] = rank;
+        }
+
+        BlueNoiseMask {
+            size,
+            values: ranks
+                .iter()
+                .map(|rank| (*rank as f64 + 0.5) / n as f64)
+                .collect(),
+        }
+    }
+
+    //tiles the plane
+    pub fn get(&self, x: u64, y: u64) -> f64 {
+        let size = self.size as u64;
+        self.values[((y % size) * size + x % size) as usize]
+    }
+}
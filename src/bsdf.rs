@@ -0,0 +1,163 @@
+use std::f64::consts::PI;
+
+use crate::render::{reflect, refract, Ray};
+use crate::vector::Vec3;
+use crate::Material;
+
+//the scattering of the path tracer written as densities, for the integrators that evaluate it
+//for directions they didn't sample themselves
+
+//a direction drawn the way the path tracer does it
+pub struct Scattered {
+    pub dir: Vec3,
+    pub weight: Vec3, //of the path tracer, for paths coming from the camera
+    pub delta: bool,
+    pub refracted: bool,
+    pub next_n: f64,
+    pub prob: f64, //of the lobe that was picked
+}
+
+fn fresnel(mat: &Material, cos: f64) -> f64 {
+    (mat.fresnel_0 + (1.0 - mat.fresnel_0) * (1.0 - cos).powf(5.0)).clamp(0.0, 1.0)
+}
+
+//normal on the side dir comes from, like the intersections give it
+pub fn facing(normal: &Vec3, dir: &Vec3) -> Vec3 {
+    if normal.dot(dir) > 0.0 {
+        normal.scale(-1.0)
+    } else {
+        normal.clone()
+    }
+}
+
+//density of normalize((1 - r) * mirror + r * c), c being cosine distributed around normal : c is
+//one of the (at most two) points of a sphere of radius r around (1 - r) * mirror that project to
+//dir, the density being carried over by the projection
+fn glossy_pdf(mirror: &Vec3, normal: &Vec3, r: f64, dir: &Vec3) -> f64 {
+    let a = mirror.scale(1.0 - r);
+    let da = dir.dot(&a);
+    let disc = da * da - a.dot(&a) + r * r;
+    if disc < 0.0 {
+        return 0.0;
+    }
+    let roots = [da + disc.sqrt(), da - disc.sqrt()];
+    let n_roots = if disc > 0.0 { 2 } else { 1 };
+    roots[..n_roots]
+        .iter()
+        .filter(|lambda| **lambda > 0.0)
+        .map(|lambda| {
+            let c = dir.scale(*lambda).minus(&a).scale(1.0 / r);
+            let cos = c.dot(normal);
+            if cos <= 0.0 {
+                0.0
+            } else {
+                cos / PI * lambda * lambda / (r * r * c.dot(dir).abs().max(1e-9))
+            }
+        })
+        .sum()
+}
+
+//solid angle density of scattering to dir_out when arriving along dir_in, 0 for delta lobes
+pub fn scatter_pdf(mat: &Material, normal: &Vec3, dir_in: &Vec3, dir_out: &Vec3) -> f64 {
+    if mat.roughness == 0.0 {
+        return 0.0;
+    }
+    let normal = facing(normal, dir_in);
+    let mirror = dir_in.minus(&normal.scale(2.0 * normal.dot(dir_in)));
+    let prob = (1.0 - mat.specularity) * fresnel(mat, -normal.dot(dir_in));
+    prob * glossy_pdf(&mirror, &normal, mat.roughness, dir_out)
+}
+
+//the bsdf of a path coming from the camera along dir_in and going on to the light along dir_out
+pub fn bsdf(mat: &Material, normal: &Vec3, dir_in: &Vec3, dir_out: &Vec3) -> Vec3 {
+    let cos = normal.dot(dir_out).abs();
+    if cos == 0.0 {
+        return Vec3::ZERO;
+    }
+    mat.albedo
+        .scale(scatter_pdf(mat, normal, dir_in, dir_out) / cos)
+}
+
+pub fn scatter(
+    mat: &Material,
+    normal: &Vec3,
+    ray: &Ray,
+    u_lobe: f64,
+    u_refraction: f64,
+    u_direction: (f64, f64),
+) -> Scattered {
+    let normal = facing(normal, &ray.dir);
+    let k_fresnel = fresnel(mat, -normal.dot(&ray.dir));
+    if u_lobe < mat.specularity {
+        Scattered {
+            dir: reflect(&ray.dir, &normal, 0.0, u_direction),
+            weight: mat.specular.scale(k_fresnel),
+            delta: true,
+            refracted: false,
+            next_n: ray.n,
+            prob: mat.specularity,
+        }
+    } else if u_refraction < 1.0 - k_fresnel {
+        let next_n = if ray.n == mat.n {
+            Material::N_AIR
+        } else {
+            mat.n
+        };
+        Scattered {
+            dir: refract(&ray.dir, &normal, ray.n, next_n),
+            weight: mat.albedo.scale(mat.transparency),
+            delta: true,
+            refracted: true,
+            next_n,
+            prob: (1.0 - mat.specularity) * (1.0 - k_fresnel),
+        }
+    } else {
+        Scattered {
+            dir: reflect(&ray.dir, &normal, mat.roughness, u_direction),
+            weight: mat.albedo.clone(),
+            delta: mat.roughness == 0.0,
+            refracted: false,
+            next_n: ray.n,
+            prob: (1.0 - mat.specularity) * k_fresnel,
+        }
+    }
+}
+
+//the weight of a scattering sampled along a path coming from a light : the path tracer's weight
+//is the one of paths coming from the camera, while the bsdf has to be evaluated the other way
+pub fn light_weight(mat: &Material, normal: &Vec3, ray: &Ray, scattered: &Scattered) -> Vec3 {
+    let dir = &scattered.dir;
+    if !scattered.delta {
+        return bsdf(mat, normal, &dir.scale(-1.0), &ray.dir.scale(-1.0))
+            .scale(normal.dot(dir).abs() / scatter_pdf(mat, normal, &ray.dir, dir));
+    }
+    if !scattered.refracted {
+        return scattered.weight.clone();
+    }
+    //the path tracer picks the lobe from the other side, and radiance gets squeezed into a
+    //smaller solid angle when leaving the denser medium
+    let cos_out = normal.dot(dir).abs();
+    let prob = (1.0 - mat.specularity) * (1.0 - fresnel(mat, cos_out));
+    scattered
+        .weight
+        .scale(prob / scattered.prob * (ray.n / scattered.next_n).powi(2))
+}
+
+//offset of rays leaving a surface, as in the path tracer
+pub const EPSILON: f64 = 0.001;
+
+//the ray leaving pos along dir at the given time, pushed off the surface of the given normal to
+//the side it leaves by, so that it doesn't hit that surface again
+pub fn spawn_ray(pos: &Vec3, normal: &Vec3, dir: Vec3, time: f64) -> Ray {
+    let side = if normal.dot(&dir) < 0.0 { -1.0 } else { 1.0 };
+    let mut ray = Ray::new(pos + &normal.scale(side * EPSILON), dir);
+    ray.time = time;
+    ray
+}
+
+//russian roulette of the path tracer, shared by the integrators : the probability that a path of
+//the given throughput goes on, which it is then brightened by, or None when it stops
+pub fn roulette(throughput: &Vec3, u: f64) -> Option<f64> {
+    let survival = throughput.x.max(throughput.y).max(throughput.z).min(1.0);
+    (u < survival).then_some(survival)
+}
@@ -0,0 +1,163 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+
+use crate::accumulator::Accumulator;
+use crate::aov::AovPixel;
+use crate::Vec3;
+
+const MAGIC: &str = "path_tracing checkpoint 5";
+
+//an interrupted render : the options it was started with, the hash of its scene, the size of its
+//strata and its accumulated samples. The header is text, the sums follow as little endian numbers
+pub struct Checkpoint {
+    pub args: Vec<String>,
+    pub scene_hash: u64,
+    pub strata: u32, //samples per pixel the stratified sampler was sized for
+    pub acc: Accumulator,
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn write_vec3(file: &mut impl Write, v: &Vec3) -> io::Result<()> {
+    for c in [v.x, v.y, v.z] {
+        file.write_all(&c.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+fn read_f64(file: &mut impl Read) -> io::Result<f64> {
+    let mut bytes = [0; 8];
+    file.read_exact(&mut bytes)?;
+    Ok(f64::from_le_bytes(bytes))
+}
+
+fn read_vec3(file: &mut impl Read) -> io::Result<Vec3> {
+    Ok(Vec3 {
+        x: read_f64(file)?,
+        y: read_f64(file)?,
+        z: read_f64(file)?,
+    })
+}
+
+impl Checkpoint {
+    //written to a temporary file first, so that a crash while saving keeps the previous one
+    pub fn save(
+        path: &str,
+        args: &[String],
+        scene_hash: u64,
+        strata: u32,
+        acc: &Accumulator,
+    ) -> io::Result<()> {
+        let tmp_path = format!("{}.tmp", path);
+        let mut file = BufWriter::new(File::create(&tmp_path)?);
+
+        writeln!(file, "{}", MAGIC)?;
+        writeln!(file, "{:016x}", scene_hash)?;
+        writeln!(file, "{}", strata)?;
+        writeln!(file, "{}", args.len())?;
+        for arg in args {
+            writeln!(file, "{}", arg)?;
+        }
+        writeln!(
+            file,
+            "{} {} {} {}",
+            acc.width,
+            acc.height,
+            acc.n_passes,
+            !acc.aovs.is_empty()
+        )?;
+
+        for samples in &acc.samples {
+            file.write_all(&samples.to_le_bytes())?;
+        }
+        for c in acc.color.iter().chain(&acc.passes).chain(&acc.splats) {
+            write_vec3(&mut file, c)?;
+        }
+        for l in &acc.luminance_sq {
+            file.write_all(&l.to_le_bytes())?;
+        }
+        for aov in &acc.aovs {
+            file.write_all(&aov.depth.to_le_bytes())?;
+            write_vec3(&mut file, &aov.normal)?;
+            write_vec3(&mut file, &aov.albedo)?;
+            write_vec3(&mut file, &aov.position)?;
+            file.write_all(&aov.object_id.to_le_bytes())?;
+            file.write_all(&aov.material_id.to_le_bytes())?;
+            file.write_all(&aov.hits.to_le_bytes())?;
+        }
+        file.flush()?;
+        drop(file);
+
+        std::fs::rename(&tmp_path, path)
+    }
+
+    pub fn load(path: &str) -> io::Result<Checkpoint> {
+        let mut file = BufReader::new(File::open(path)?);
+        let mut next_line = || -> io::Result<String> {
+            let mut line = String::new();
+            file.read_line(&mut line)?;
+            Ok(String::from(line.trim_end_matches('\n')))
+        };
+
+        if next_line()? != MAGIC {
+            return Err(invalid("not a checkpoint"));
+        }
+        let scene_hash =
+            u64::from_str_radix(&next_line()?, 16).map_err(|_| invalid("bad scene hash"))?;
+        let strata: u32 = next_line()?.parse().map_err(|_| invalid("bad strata"))?;
+        let n_args: usize = next_line()?.parse().map_err(|_| invalid("bad arg count"))?;
+        let args = (0..n_args)
+            .map(|_| next_line())
+            .collect::<io::Result<Vec<String>>>()?;
+        let size = next_line()?;
+        let size: Vec<&str> = size.split(' ').collect();
+        let (width, height, n_passes, keep_aovs) = match size[..] {
+            [w, h, n, a] => (
+                w.parse().map_err(|_| invalid("bad width"))?,
+                h.parse().map_err(|_| invalid("bad height"))?,
+                n.parse().map_err(|_| invalid("bad pass count"))?,
+                a == "true",
+            ),
+            _ => return Err(invalid("bad size")),
+        };
+
+        let mut acc = Accumulator::new(width, height, n_passes, keep_aovs);
+        for samples in acc.samples.iter_mut() {
+            let mut bytes = [0; 4];
+            file.read_exact(&mut bytes)?;
+            *samples = u32::from_le_bytes(bytes);
+        }
+        for c in acc
+            .color
+            .iter_mut()
+            .chain(acc.passes.iter_mut())
+            .chain(acc.splats.iter_mut())
+        {
+            *c = read_vec3(&mut file)?;
+        }
+        for l in acc.luminance_sq.iter_mut() {
+            *l = read_f64(&mut file)?;
+        }
+        for aov in acc.aovs.iter_mut() {
+            *aov = AovPixel {
+                depth: read_f64(&mut file)?,
+                normal: read_vec3(&mut file)?,
+                albedo: read_vec3(&mut file)?,
+                position: read_vec3(&mut file)?,
+                object_id: read_f64(&mut file)?,
+                material_id: read_f64(&mut file)?,
+                samples: 0.0,
+                hits: read_f64(&mut file)?,
+            };
+        }
+
+        Ok(Checkpoint {
+            args,
+            scene_hash,
+            strata,
+            acc,
+        })
+    }
+}
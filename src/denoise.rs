@@ -0,0 +1,88 @@
+use crate::framebuffer::FrameBuffer;
+use crate::tonemap::luminance;
+use crate::Vec3;
+
+//B3 spline, the à-trous kernel in each direction
+const KERNEL: [f64; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+//edge-avoiding à-trous wavelet filter (Dammertz et al. 2010) : a 5x5 kernel dilated at each
+//iteration, its weights dropping across edges of the color, normal and albedo buffers
+pub struct Denoiser {
+    pub iterations: u32,
+    pub sigma_color: f64, //halved at each iteration, as details get coarser
+    pub sigma_normal: f64,
+    pub sigma_albedo: f64,
+    pub keep_raw: bool, //also save the noisy render
+}
+
+impl Denoiser {
+    pub fn new(strength: f64, keep_raw: bool) -> Denoiser {
+        Denoiser {
+            iterations: 5,
+            sigma_color: strength,
+            sigma_normal: 0.1,
+            sigma_albedo: 0.1,
+            keep_raw,
+        }
+    }
+
+    pub fn denoise(
+        &self,
+        color: &FrameBuffer,
+        albedo: &FrameBuffer,
+        normal: &FrameBuffer,
+    ) -> FrameBuffer {
+        let (width, height) = (color.width as i64, color.height as i64);
+        let mut current = color.pixels.clone();
+
+        for iteration in 0..self.iterations {
+            let step = 1i64 << iteration;
+            let sigma_color = self.sigma_color / 2f64.powi(iteration as i32);
+            let mut next = Vec::with_capacity(current.len());
+            //edges are found on range compressed colors, so that fireflies don't stand out
+            let compressed: Vec<Vec3> = current
+                .iter()
+                .map(|c| c.scale(1.0 / (1.0 + luminance(c).max(0.0))))
+                .collect();
+
+            for j in 0..height {
+                for i in 0..width {
+                    let p = (j * width + i) as usize;
+                    let mut sum = Vec3::ZERO;
+                    let mut weight_sum = 0.0;
+                    for (dj, kj) in KERNEL.iter().enumerate() {
+                        for (di, ki) in KERNEL.iter().enumerate() {
+                            let qi = i + (di as i64 - 2) * step;
+                            let qj = j + (dj as i64 - 2) * step;
+                            if qi < 0 || qi >= width || qj < 0 || qj >= height {
+                                continue;
+                            }
+                            let q = (qj * width + qi) as usize;
+
+                            let d_color = compressed[q].minus(&compressed[p]).norm().powi(2);
+                            let d_normal = normal.pixels[q].minus(&normal.pixels[p]).norm().powi(2);
+                            let d_albedo = albedo.pixels[q].minus(&albedo.pixels[p]).norm().powi(2);
+                            let weight = ki
+                                * kj
+                                * (-d_color / (sigma_color * sigma_color)
+                                    - d_normal / (self.sigma_normal * self.sigma_normal)
+                                    - d_albedo / (self.sigma_albedo * self.sigma_albedo))
+                                    .exp();
+
+                            sum = &sum + &current[q].scale(weight);
+                            weight_sum += weight;
+                        }
+                    }
+                    next.push(sum.scale(1.0 / weight_sum));
+                }
+            }
+            current = next;
+        }
+
+        FrameBuffer {
+            width: color.width,
+            height: color.height,
+            pixels: current,
+        }
+    }
+}
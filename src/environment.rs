@@ -0,0 +1,282 @@
+use std::f64::consts::PI;
+use std::fmt;
+use std::fs::File;
+use std::io::BufReader;
+
+use image::codecs::hdr::HdrDecoder;
+use image::ImageResult;
+
+use crate::scene::fnv1a;
+use crate::tonemap::luminance;
+use crate::vector::Vec3;
+
+//light coming from infinitely far away in every direction, held in an equirectangular image :
+//the columns go around the vertical axis (y) and the rows from straight up to straight down.
+//Directions are importance sampled from the luminance of the pixels, weighted by the solid angle
+//they cover, and from the sun if there is one
+pub struct Environment {
+    description: String,
+    width: usize,
+    height: usize,
+    pixels: Vec<Vec3>, //row by row, scaled by the intensity
+    rotation: f64,     //in turns around the vertical axis
+    rows: Vec<f64>,    //cumulated probabilities of the rows, starting at 0
+    columns: Vec<f64>, //cumulated probabilities of the columns within each row, starting at 0
+    sun: Option<Sun>,
+    sun_prob: f64, //of sampling the sun rather than the image
+}
+
+//a disk of light too small and bright to be held in the pixels
+pub struct Sun {
+    pub dir: Vec3,
+    pub radiance: Vec3,
+    pub cos_radius: f64, //cosine of its angular radius
+}
+
+impl Sun {
+    pub fn solid_angle(&self) -> f64 {
+        2.0 * PI * (1.0 - self.cos_radius)
+    }
+
+    fn covers(&self, dir: &Vec3) -> bool {
+        self.dir.dot(dir) >= self.cos_radius
+    }
+
+    //uniformly distributed over the disk
+    fn sample(&self, u: (f64, f64)) -> Vec3 {
+        Vec3::uniform_cone(&self.dir, self.cos_radius, u)
+    }
+}
+
+//the description is enough to tell environments apart, for the scene hash
+impl fmt::Debug for Environment {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Environment({})", self.description)
+    }
+}
+
+//cumulated values, normalized to end at 1 (spread evenly if they're all 0)
+fn cumulate(values: &[f64]) -> Vec<f64> {
+    let total: f64 = values.iter().sum();
+    let mut cumulated = vec![0.0];
+    let mut sum = 0.0;
+    for (k, value) in values.iter().enumerate() {
+        sum += value;
+        cumulated.push(if total > 0.0 {
+            sum / total
+        } else {
+            (k + 1) as f64 / values.len() as f64
+        });
+    }
+    cumulated
+}
+
+//the interval of cumulated that u falls in, and where in it
+fn find(cumulated: &[f64], u: f64) -> (usize, f64) {
+    let k = (cumulated.partition_point(|&c| c <= u) - 1).min(cumulated.len() - 2);
+    let width = cumulated[k + 1] - cumulated[k];
+    let offset = if width > 0.0 {
+        (u - cumulated[k]) / width
+    } else {
+        0.5
+    };
+    (k, offset.clamp(0.0, 1.0))
+}
+
+//size and rgb values of an image file. image::open gives radiance hdr files tone mapped to 8
+//bits, so they are decoded apart
+fn read_rgb(path: &str) -> ImageResult<((usize, usize), Vec<f32>)> {
+    if path.to_lowercase().ends_with(".hdr") {
+        let decoder = HdrDecoder::new(BufReader::new(File::open(path)?))?;
+        let meta = decoder.metadata();
+        let rgb = decoder.read_image_hdr()?.iter().flat_map(|p| p.0).collect();
+        Ok(((meta.width as usize, meta.height as usize), rgb))
+    } else {
+        let image = image::open(path)?.into_rgb32f();
+        let size = (image.width() as usize, image.height() as usize);
+        Ok((size, image.into_raw()))
+    }
+}
+
+//the direction seen at image coordinates (0 to 1) turned by rotation
+fn direction((u, v): (f64, f64), rotation: f64) -> Vec3 {
+    let phi = 2.0 * PI * (u - 0.5 + rotation);
+    let theta = PI * v;
+    Vec3 {
+        x: theta.sin() * phi.sin(),
+        y: theta.cos(),
+        z: -theta.sin() * phi.cos(),
+    }
+}
+
+impl Environment {
+    //rotation in degrees, turning the environment around the vertical axis
+    pub fn load(path: &str, rotation: f64, intensity: f64) -> Result<Environment, String> {
+        let (size, rgb) = read_rgb(path).map_err(|e| e.to_string())?;
+        //the pixels go in the description, so that resuming notices a changed file
+        let pixels_hash = fnv1a(rgb.iter().flat_map(|c| c.to_le_bytes()));
+        let pixels = rgb
+            .chunks(3)
+            .map(|p| Vec3 {
+                x: p[0] as f64,
+                y: p[1] as f64,
+                z: p[2] as f64,
+            })
+            .collect();
+        Ok(Environment::new(
+            format!(
+                "{} ({:016x}), rotation {}, intensity {}",
+                path, pixels_hash, rotation, intensity
+            ),
+            size,
+            pixels,
+            rotation,
+            intensity,
+            None,
+        ))
+    }
+
+    //an environment given by the radiance of every direction, seen through pixels of the given
+    //size, and the sun
+    pub fn from_fn(
+        description: String,
+        (width, height): (usize, usize),
+        radiance: impl Fn(&Vec3) -> Vec3,
+        intensity: f64,
+        sun: Option<Sun>,
+    ) -> Environment {
+        let pixels = (0..height)
+            .flat_map(|row| (0..width).map(move |column| (column, row)))
+            .map(|(column, row)| {
+                radiance(&direction(
+                    (
+                        (column as f64 + 0.5) / width as f64,
+                        (row as f64 + 0.5) / height as f64,
+                    ),
+                    0.0,
+                ))
+            })
+            .collect();
+        Environment::new(description, (width, height), pixels, 0.0, intensity, sun)
+    }
+
+    fn new(
+        description: String,
+        (width, height): (usize, usize),
+        pixels: Vec<Vec3>,
+        rotation: f64,
+        intensity: f64,
+        sun: Option<Sun>,
+    ) -> Environment {
+        let pixels: Vec<Vec3> = pixels.iter().map(|p| p.scale(intensity)).collect();
+        let sun = sun.map(|sun| Sun {
+            radiance: sun.radiance.scale(intensity),
+            ..sun
+        });
+
+        //the rows near the poles cover less solid angle
+        let weights: Vec<f64> = (0..height)
+            .flat_map(|row| {
+                let sin_theta = (PI * (row as f64 + 0.5) / height as f64).sin();
+                let pixels = &pixels;
+                (0..width).map(move |column| luminance(&pixels[row * width + column]) * sin_theta)
+            })
+            .collect();
+        let row_weights: Vec<f64> = weights.chunks(width).map(|row| row.iter().sum()).collect();
+        let columns = weights.chunks(width).flat_map(cumulate).collect();
+
+        //the sun and the image are sampled in proportion to the light they give
+        let image_power = row_weights.iter().sum::<f64>() * 2.0 * PI * PI / (width * height) as f64;
+        let sun_prob = match &sun {
+            Some(sun) => {
+                let sun_power = luminance(&sun.radiance) * sun.solid_angle();
+                if sun_power + image_power > 0.0 {
+                    sun_power / (sun_power + image_power)
+                } else {
+                    0.0
+                }
+            }
+            None => 0.0,
+        };
+
+        Environment {
+            description,
+            width,
+            height,
+            pixels,
+            rotation: rotation / 360.0,
+            rows: cumulate(&row_weights),
+            columns,
+            sun,
+            sun_prob,
+        }
+    }
+
+    //image coordinates (0 to 1) of a direction
+    fn to_image(&self, dir: &Vec3) -> (f64, f64) {
+        let u = 0.5 + dir.x.atan2(-dir.z) / (2.0 * PI) - self.rotation;
+        let v = dir.y.clamp(-1.0, 1.0).acos() / PI;
+        (u - u.floor(), v)
+    }
+
+    fn pixel(&self, (u, v): (f64, f64)) -> (usize, usize) {
+        (
+            ((u * self.width as f64) as usize).min(self.width - 1),
+            ((v * self.height as f64) as usize).min(self.height - 1),
+        )
+    }
+
+    //radiance coming from direction dir
+    pub fn radiance(&self, dir: &Vec3) -> Vec3 {
+        let (column, row) = self.pixel(self.to_image(dir));
+        let radiance = &self.pixels[row * self.width + column];
+        match &self.sun {
+            Some(sun) if sun.covers(dir) => radiance + &sun.radiance,
+            _ => radiance.clone(),
+        }
+    }
+
+    //solid angle density of the directions sampled from the image
+    fn image_pdf(&self, dir: &Vec3) -> f64 {
+        let (u, v) = self.to_image(dir);
+        let (column, row) = self.pixel((u, v));
+        let sin_theta = (PI * v).sin();
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+        let columns = &self.columns[row * (self.width + 1)..(row + 1) * (self.width + 1)];
+        let prob = (self.rows[row + 1] - self.rows[row]) * (columns[column + 1] - columns[column]);
+        prob * (self.width * self.height) as f64 / (2.0 * PI * PI * sin_theta)
+    }
+
+    //solid angle density of sample giving dir
+    pub fn pdf(&self, dir: &Vec3) -> f64 {
+        let sun_pdf = match &self.sun {
+            Some(sun) if sun.covers(dir) => self.sun_prob / sun.solid_angle(),
+            _ => 0.0,
+        };
+        sun_pdf + (1.0 - self.sun_prob) * self.image_pdf(dir)
+    }
+
+    //a direction drawn in proportion to the light coming from it, with its radiance and density
+    pub fn sample(&self, u: (f64, f64)) -> (Vec3, Vec3, f64) {
+        let dir = match &self.sun {
+            Some(sun) if u.0 < self.sun_prob => sun.sample((u.0 / self.sun_prob, u.1)),
+            _ => {
+                let u_column = (u.0 - self.sun_prob) / (1.0 - self.sun_prob);
+                let (row, v_offset) = find(&self.rows, u.1);
+                let columns = &self.columns[row * (self.width + 1)..(row + 1) * (self.width + 1)];
+                let (column, u_offset) = find(columns, u_column);
+                direction(
+                    (
+                        (column as f64 + u_offset) / self.width as f64,
+                        (row as f64 + v_offset) / self.height as f64,
+                    ),
+                    self.rotation,
+                )
+            }
+        };
+        let (radiance, pdf) = (self.radiance(&dir), self.pdf(&dir));
+        (dir, radiance, pdf)
+    }
+}
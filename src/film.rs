@@ -0,0 +1,118 @@
+use crate::vector::{Mat4, Vec3};
+
+//linear sRGB <-> XYZ and the Bradford cone response, used for white balance
+const SRGB_TO_XYZ: [[f64; 3]; 3] = [
+    [0.4124564, 0.3575761, 0.1804375],
+    [0.2126729, 0.7151522, 0.0721750],
+    [0.0193339, 0.1191920, 0.9503041],
+];
+const XYZ_TO_SRGB: [[f64; 3]; 3] = [
+    [3.2404542, -1.5371385, -0.4985314],
+    [-0.9692660, 1.8760108, 0.0415560],
+    [0.0556434, -0.2040259, 1.0572252],
+];
+const XYZ_TO_LMS: [[f64; 3]; 3] = [
+    [0.8951, 0.2664, -0.1614],
+    [-0.7502, 1.7135, 0.0367],
+    [0.0389, -0.0685, 1.0296],
+];
+const LMS_TO_XYZ: [[f64; 3]; 3] = [
+    [0.9869929, -0.1470543, 0.1599627],
+    [0.4323053, 0.5183603, 0.0492912],
+    [-0.0085287, 0.0400428, 0.9684867],
+];
+const D65_WHITE: Vec3 = Vec3 {
+    x: 0.95047,
+    y: 1.0,
+    z: 1.08883,
+};
+
+//exposure value at ISO 100 of a camera setting (aperture as an f-number, shutter in seconds)
+pub fn ev100(iso: f64, shutter: f64, f_number: f64) -> f64 {
+    (f_number * f_number / shutter * 100.0 / iso).log2()
+}
+
+//XYZ (with Y = 1) of a black body at the given temperature, using the Kim et al. fit of the
+//planckian locus, valid from 1667K to 25000K
+pub fn black_body_xyz(kelvin: f64) -> Vec3 {
+    let t = kelvin.clamp(1667.0, 25000.0);
+    let (t2, t3) = (t * t, t * t * t);
+    let x = if t <= 4000.0 {
+        -0.2661239e9 / t3 - 0.2343589e6 / t2 + 0.8776956e3 / t + 0.179910
+    } else {
+        -3.0258469e9 / t3 + 2.1070379e6 / t2 + 0.2226347e3 / t + 0.240390
+    };
+    let (x2, x3) = (x * x, x * x * x);
+    let y = if t <= 2222.0 {
+        -1.1063814 * x3 - 1.34811020 * x2 + 2.18555832 * x - 0.20219683
+    } else if t <= 4000.0 {
+        -0.9549476 * x3 - 1.37418593 * x2 + 2.09137015 * x - 0.16748867
+    } else {
+        3.0817580 * x3 - 5.87338670 * x2 + 3.75112997 * x - 0.37001483
+    };
+
+    Vec3 {
+        x: x / y,
+        y: 1.0,
+        z: (1.0 - x - y) / y,
+    }
+}
+
+//the film stage : turns the radiance reaching the sensor into the linear color that gets output
+pub struct Film {
+    pub exposure: f64,               //scale applied to radiance
+    pub white_balance: Option<Mat4>, //linear sRGB to linear sRGB, adapting the illuminant to D65
+    pub vignetting: f64,             //0 for none, 1 for the full natural cos^4 falloff
+}
+
+impl Default for Film {
+    fn default() -> Film {
+        Film {
+            exposure: 1.0,
+            white_balance: None,
+            vignetting: 0.0,
+        }
+    }
+}
+
+impl Film {
+    //ev100 is None for an unexposed film (radiance is output as is), white_balance is the color
+    //temperature of the scene illuminant that should appear neutral
+    pub fn new(ev100: Option<f64>, white_balance: Option<f64>, vignetting: f64) -> Film {
+        Film {
+            //saturation based sensitivity, with the usual 1.2 headroom of the sensor
+            exposure: ev100.map_or(1.0, |ev| 1.0 / (1.2 * 2f64.powf(ev))),
+            white_balance: white_balance.map(Film::white_balance_matrix),
+            vignetting,
+        }
+    }
+
+    //von Kries adaptation in the Bradford cone space, from a black body illuminant to D65
+    fn white_balance_matrix(kelvin: f64) -> Mat4 {
+        let to_lms = Mat4::from_rows3(XYZ_TO_LMS);
+        let source = to_lms.apply_dir3(&black_body_xyz(kelvin)).as_vec3();
+        let target = to_lms.apply_dir3(&D65_WHITE).as_vec3();
+        let gains = Mat4::from_rows3([
+            [target.x / source.x, 0.0, 0.0],
+            [0.0, target.y / source.y, 0.0],
+            [0.0, 0.0, target.z / source.z],
+        ]);
+
+        Mat4::from_rows3(XYZ_TO_SRGB)
+            .product(&Mat4::from_rows3(LMS_TO_XYZ))
+            .product(&gains)
+            .product(&to_lms)
+            .product(&Mat4::from_rows3(SRGB_TO_XYZ))
+    }
+
+    //cos_theta is the cosine between the primary ray and the optical axis
+    pub fn develop(&self, radiance: &Vec3, cos_theta: f64) -> Vec3 {
+        let falloff = 1.0 - self.vignetting * (1.0 - cos_theta.max(0.0).powi(4));
+        let exposed = radiance.scale(self.exposure * falloff);
+
+        match &self.white_balance {
+            Some(matrix) => matrix.apply_dir3(&exposed).as_vec3(),
+            None => exposed,
+        }
+    }
+}
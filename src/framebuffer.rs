@@ -0,0 +1,210 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use exr::prelude::{
+    f16, AnyChannel, AnyChannels, Encoding, FlatSamples, Image, Layer, LayerAttributes, SmallVec,
+    WritableImage,
+};
+use image::codecs::hdr::HdrEncoder;
+use image::{ImageBuffer, Rgb, RgbImage};
+
+use crate::tonemap::{srgb_oetf, ToneMap};
+use crate::Vec3;
+
+//an image file to write the render to, its format being given by the extension
+#[derive(Clone)]
+pub struct Output {
+    pub path: String,
+    pub tonemap: Option<ToneMap>, //8 bit formats clamp when None, float formats stay linear
+    pub exposure: f64,            //in stops, applied before tone mapping
+    pub exr_half: bool,
+}
+
+//the floating point film the renderer accumulates into, row by row from the top
+pub struct FrameBuffer {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<Vec3>,
+}
+
+fn to_io_error<E: ToString>(e: E) -> io::Error {
+    io::Error::other(e.to_string())
+}
+
+//writes named float channels (row by row from the top) to a single part OpenEXR file. Channels
+//named like layer.R are grouped into layers by compositing software
+pub fn write_exr(
+    path: &str,
+    width: u32,
+    height: u32,
+    channels: Vec<(String, Vec<f32>)>,
+    half: bool,
+) -> io::Result<()> {
+    let channels: SmallVec<[AnyChannel<FlatSamples>; 4]> = channels
+        .into_iter()
+        .map(|(name, samples)| {
+            let samples = if half {
+                FlatSamples::F16(samples.into_iter().map(f16::from_f32).collect())
+            } else {
+                FlatSamples::F32(samples)
+            };
+            AnyChannel::new(name.as_str(), samples)
+        })
+        .collect();
+
+    let layer = Layer::new(
+        (width as usize, height as usize),
+        LayerAttributes::named("render"),
+        Encoding::SMALL_LOSSLESS,
+        AnyChannels::sort(channels),
+    );
+    Image::from_layer(layer)
+        .write()
+        .to_file(path)
+        .map_err(to_io_error)
+}
+
+//lowercase extension of a path, which gives the image format
+pub fn extension(path: &str) -> String {
+    Path::new(path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_lowercase()
+}
+
+//the same output for another image next to it, like render_depth.png for render.png
+pub fn sibling_output(output: &Output, suffix: &str) -> Output {
+    let path = Path::new(&output.path);
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("png");
+    Output {
+        path: format!(
+            "{}_{}.{}",
+            path.with_extension("").display(),
+            suffix,
+            extension
+        ),
+        ..output.clone()
+    }
+}
+
+impl FrameBuffer {
+    pub fn new(width: u32, height: u32) -> FrameBuffer {
+        FrameBuffer {
+            width,
+            height,
+            pixels: vec![Vec3::ZERO; (width * height) as usize],
+        }
+    }
+
+    pub fn get(&self, i: u32, j: u32) -> &Vec3 {
+        &self.pixels[(j * self.width + i) as usize]
+    }
+
+    pub fn put_pixel(&mut self, i: u32, j: u32, color: Vec3) {
+        self.pixels[(j * self.width + i) as usize] = color;
+    }
+
+    pub fn channel(&self, f: fn(&Vec3) -> f64) -> Vec<f32> {
+        self.pixels.iter().map(|p| f(p) as f32).collect()
+    }
+
+    //exr channels named layer.name, from x, y and z in order (just x for a single name)
+    pub fn exr_channels(&self, layer: &str, names: &[&str]) -> Vec<(String, Vec<f32>)> {
+        let getters: [fn(&Vec3) -> f64; 3] = [|p| p.x, |p| p.y, |p| p.z];
+        names
+            .iter()
+            .zip(getters.iter())
+            .map(|(name, getter)| {
+                let full_name = if layer.is_empty() {
+                    name.to_string()
+                } else {
+                    format!("{}.{}", layer, name)
+                };
+                (full_name, self.channel(*getter))
+            })
+            .collect()
+    }
+
+    //tone mapped and sRGB encoded
+    pub fn to_rgb8(&self, tonemap: &ToneMap) -> RgbImage {
+        let f_to_u8 = |f: f64| (255.0 * srgb_oetf(f)).round() as u8;
+        ImageBuffer::from_fn(self.width, self.height, |i, j| {
+            let color = tonemap.apply(self.get(i, j));
+            Rgb([f_to_u8(color.x), f_to_u8(color.y), f_to_u8(color.z)])
+        })
+    }
+
+    //exposed, and tone mapped when the output has a tone mapping. The result is still linear
+    pub fn developed(&self, output: &Output) -> FrameBuffer {
+        let scale = 2f64.powf(output.exposure);
+        FrameBuffer {
+            width: self.width,
+            height: self.height,
+            pixels: self
+                .pixels
+                .iter()
+                .map(|p| match &output.tonemap {
+                    Some(tonemap) => tonemap.apply(&p.scale(scale)),
+                    None => p.scale(scale),
+                })
+                .collect(),
+        }
+    }
+
+    //picks the format from the extension : exr, hdr (radiance RGBE) and pfm keep the full range,
+    //anything else goes through an 8 bit image
+    pub fn save(&self, output: &Output) -> io::Result<()> {
+        let path = output.path.as_str();
+        let extension = extension(path);
+
+        //8 bit images are always tone mapped, clamping by default
+        let img = self.developed(&Output {
+            tonemap: None,
+            ..output.clone()
+        });
+
+        match extension.as_str() {
+            "exr" => write_exr(
+                path,
+                self.width,
+                self.height,
+                self.developed(output).exr_channels("", &["R", "G", "B"]),
+                output.exr_half,
+            ),
+            "hdr" => self.developed(output).save_hdr(path),
+            "pfm" => self.developed(output).save_pfm(path),
+            _ => img
+                .to_rgb8(output.tonemap.as_ref().unwrap_or(&ToneMap::Clamp))
+                .save(path)
+                .map_err(to_io_error),
+        }
+    }
+
+    fn save_hdr(&self, path: &str) -> io::Result<()> {
+        let pixels: Vec<Rgb<f32>> = self
+            .pixels
+            .iter()
+            .map(|p| Rgb([p.x as f32, p.y as f32, p.z as f32]))
+            .collect();
+        HdrEncoder::new(BufWriter::new(File::create(path)?))
+            .encode(&pixels, self.width as usize, self.height as usize)
+            .map_err(to_io_error)
+    }
+
+    //portable float map : little endian (negative scale), rows from the bottom
+    fn save_pfm(&self, path: &str) -> io::Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+        write!(file, "PF\n{} {}\n-1.0\n", self.width, self.height)?;
+        for j in (0..self.height).rev() {
+            for i in 0..self.width {
+                let p = self.get(i, j);
+                for c in [p.x, p.y, p.z] {
+                    file.write_all(&(c as f32).to_le_bytes())?;
+                }
+            }
+        }
+        file.flush()
+    }
+}
@@ -0,0 +1,433 @@
+use crate::aov::heat_color;
+use crate::bdpt::Bdpt;
+use crate::bsdf::{bsdf, facing, roulette, scatter_pdf};
+use crate::environment::Environment;
+use crate::mlt::Mlt;
+use crate::passes;
+use crate::render::{intersect, reflect, refract, Context, Intersection, Ray};
+use crate::sampler::Sampler;
+use crate::scene::Scene;
+use crate::sppm::Sppm;
+use crate::vector::Vec3;
+use crate::Material;
+
+//what is seen along a camera ray. Its first intersection is given, as pixel_shader also needs it
+//for the aovs. Contributions to the light path passes are added to passes
+pub trait Integrator: Send + Sync {
+    fn radiance<'a>(
+        &self,
+        ctx: &'a Context,
+        ray: Ray,
+        hit: Intersection<'a>,
+        sampler: &mut Sampler,
+        passes: &mut [Vec3],
+        splats: &mut Vec<Splat>,
+    ) -> Vec3;
+}
+
+//light reaching the camera elsewhere than through the pixel being rendered
+pub struct Splat {
+    pub x: u32,
+    pub y: u32,
+    pub color: Vec3, //developed
+}
+
+//the options of the integrators, each one using its own
+pub struct Options {
+    pub path_tracer: PathTracer,
+    pub ao_distance: f64,
+    pub photons: u32, //per photon map
+    pub photon_radius: f64,
+    pub mutations: u32, //per sample, for metropolis
+    pub bootstrap: u32,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            path_tracer: PathTracer::default(),
+            ao_distance: 0.5,
+            photons: 50_000,
+            photon_radius: 0.05,
+            mutations: 32,
+            bootstrap: 100_000,
+        }
+    }
+}
+
+//integrators by name, the debug ones showing what the path tracer sees
+pub fn from_name(name: &str, options: Options, scene: &Scene) -> Option<Box<dyn Integrator>> {
+    let path_tracer = options.path_tracer;
+    match name {
+        "path" => Some(Box::new(path_tracer)),
+        "bdpt" => Some(Box::new(Bdpt::new(scene, &path_tracer))),
+        "sppm" => Some(Box::new(Sppm::new(
+            scene,
+            &path_tracer,
+            options.photons,
+            options.photon_radius,
+        ))),
+        "mlt" => Some(Box::new(Mlt::new(
+            path_tracer,
+            options.mutations,
+            options.bootstrap,
+        ))),
+        "ao" => Some(Box::new(AmbientOcclusion {
+            distance: options.ao_distance,
+        })),
+        "normals" => Some(Box::new(Normals)),
+        "albedo" => Some(Box::new(Albedo)),
+        "depth" => Some(Box::new(Depth)),
+        "bounces" => Some(Box::new(BounceCount { path_tracer })),
+        _ => None,
+    }
+}
+
+pub struct PathTracer {
+    pub bounces: u8,        //hard cap on the path length
+    pub roulette_depth: u8, //bounces before russian roulette starts
+}
+
+impl Default for PathTracer {
+    fn default() -> PathTracer {
+        PathTracer {
+            bounces: 64,
+            roulette_depth: 3,
+        }
+    }
+}
+
+//adds light reaching the camera along the path made of events to the passes it matches
+fn add_to_passes(ctx: &Context, events: &[u8], acc_passes: &mut [Vec3], light: &Vec3) {
+    for (pass, acc) in ctx.passes.iter().zip(acc_passes.iter_mut()) {
+        if pass.expression.matches(events) {
+            *acc = &*acc + light;
+        }
+    }
+}
+
+//weight of a sample drawn with density pdf, against another way of drawing it with density other
+fn power_heuristic(pdf: f64, other: f64) -> f64 {
+    if other == 0.0 {
+        return 1.0;
+    }
+    pdf * pdf / (pdf * pdf + other * other)
+}
+
+//whether nothing lies between the hit and distance along dir, dir leaving on the side the ray
+//came from
+fn unoccluded(ctx: &Context, ray: &Ray, int: &Intersection, dir: &Vec3, distance: f64) -> bool {
+    let normal = facing(&int.normal, &ray.dir);
+    let mut shadow_ray = Ray::new(&int.pos + &normal.scale(0.001), dir.clone());
+    shadow_ray.time = ray.time;
+    let occluder = intersect(&shadow_ray, &ctx.scene);
+    !occluder.hit || occluder.dist >= distance - 0.002
+}
+
+impl PathTracer {
+    //the radiance and the number of surfaces the path bounced on
+    pub fn trace<'a>(
+        &self,
+        ctx: &'a Context,
+        mut ray: Ray,
+        hit: Intersection<'a>,
+        sampler: &mut Sampler,
+        acc_passes: &mut [Vec3],
+    ) -> (Vec3, u8) {
+        let mut int = hit;
+        let mut events = vec![passes::CAMERA];
+        let mut iter = 0;
+        //density of the last direction scattered, None when it couldn't be sampled another way
+        let mut scatter_density = None;
+        while int.hit {
+            let dotp = -int.normal.dot(&ray.dir);
+            let k_fresnel = int.mat.fresnel_0 + (1.0 - int.mat.fresnel_0) * (1.0 - dotp).powf(5.0);
+
+            let emitted = int.mat.emissive.mult(&ray.color);
+            if !ctx.passes.is_empty() && emitted.norm() > 0.0 {
+                events.push(passes::EMISSION);
+                add_to_passes(ctx, &events, acc_passes, &emitted);
+                events.pop();
+            }
+            ray.emitted = &ray.emitted + &emitted;
+
+            //dir stuff TODO refactor this mess
+            sampler.start_bounce(iter as u32);
+            let is_specular_bounce = sampler.get_1d() < int.mat.specularity;
+            let u_refraction = sampler.get_1d();
+            let u_direction = sampler.get_2d();
+            let u_roulette = sampler.get_1d();
+            let u_environment = sampler.get_2d();
+            let u_punctual = (sampler.get_1d(), sampler.get_2d());
+
+            //next event estimation, for the non delta lobe
+            if int.mat.roughness > 0.0 {
+                let mut light = Vec3::ZERO;
+                if let Some(environment) = &ctx.scene.environment {
+                    let sampled =
+                        self.environment_light(ctx, environment, &ray, &int, u_environment);
+                    light = &light + &sampled;
+                }
+                if !ctx.scene.punctual_lights.is_empty() {
+                    light = &light + &self.punctual_light(ctx, &ray, &int, u_punctual);
+                }
+                if !ctx.passes.is_empty() && light.norm() > 0.0 {
+                    events.extend([passes::DIFFUSE, passes::EMISSION]);
+                    add_to_passes(ctx, &events, acc_passes, &light);
+                    events.truncate(events.len() - 2);
+                }
+                ray.emitted = &ray.emitted + &light;
+            }
+
+            let dir_in = ray.dir.clone();
+            scatter_density = None;
+            if is_specular_bounce {
+                ray.dir = reflect(&ray.dir, &int.normal, 0.0, u_direction);
+                ray.color = ray.color.mult(&int.mat.specular).scale(k_fresnel);
+                events.push(passes::SPECULAR);
+            } else {
+                let is_refraction = u_refraction < (1.0 - k_fresnel);
+                if is_refraction {
+                    let next_n = if ray.n == int.mat.n {
+                        Material::N_AIR
+                    } else {
+                        int.mat.n
+                    };
+                    ray.dir = refract(&ray.dir, &int.normal, ray.n, next_n);
+                    ray.n = next_n;
+                    let scaler = int.mat.transparency;
+                    let new_color = ray.color.mult(&int.mat.albedo).scale(scaler);
+                    ray.color = new_color;
+                    ray.origin = &int.pos + &int.normal.scale(-0.001);
+                    events.push(passes::TRANSMISSION);
+                } else {
+                    ray.dir = reflect(&ray.dir, &int.normal, int.mat.roughness, u_direction);
+                    //let cos_theta = ray.dir.dot(&int.normal);
+                    ray.color = ray.color.mult(&int.mat.albedo);
+                    ray.origin = &int.pos + &int.normal.scale(0.001);
+                    events.push(if int.mat.roughness == 0.0 {
+                        passes::SPECULAR
+                    } else {
+                        scatter_density =
+                            Some(scatter_pdf(int.mat, &int.normal, &dir_in, &ray.dir));
+                        passes::DIFFUSE
+                    });
+                }
+            }
+
+            //the hard cap, in case roulette keeps a path going around between mirrors
+            iter += 1;
+            if iter > self.bounces {
+                break;
+            }
+
+            //russian roulette : dim paths are terminated with a probability that grows as their
+            //throughput drops, the survivors being brightened to make up for it
+            if iter > self.roulette_depth {
+                let Some(survival) = roulette(&ray.color, u_roulette) else {
+                    break;
+                };
+                ray.color = ray.color.scale(1.0 / survival);
+            }
+
+            int = intersect(&ray, &ctx.scene);
+        }
+
+        //the path left the scene
+        if let (false, Some(environment)) = (int.hit, &ctx.scene.environment) {
+            let weight = scatter_density.map_or(1.0, |density| {
+                power_heuristic(density, environment.pdf(&ray.dir))
+            });
+            let emitted = environment
+                .radiance(&ray.dir)
+                .mult(&ray.color)
+                .scale(weight);
+            if !ctx.passes.is_empty() && emitted.norm() > 0.0 {
+                events.push(passes::EMISSION);
+                add_to_passes(ctx, &events, acc_passes, &emitted);
+            }
+            ray.emitted = &ray.emitted + &emitted;
+        }
+        (ray.emitted, iter)
+    }
+
+    //light of the environment reaching the hit directly and scattered toward the camera, the
+    //direction being drawn from the environment and weighted against scattering finding it
+    fn environment_light(
+        &self,
+        ctx: &Context,
+        environment: &Environment,
+        ray: &Ray,
+        int: &Intersection,
+        u: (f64, f64),
+    ) -> Vec3 {
+        let (dir, radiance, pdf) = environment.sample(u);
+        let f = bsdf(int.mat, &int.normal, &ray.dir, &dir);
+        if pdf == 0.0 || f.norm() == 0.0 {
+            return Vec3::ZERO;
+        }
+        if !unoccluded(ctx, ray, int, &dir, f64::INFINITY) {
+            return Vec3::ZERO;
+        }
+        let weight = power_heuristic(pdf, scatter_pdf(int.mat, &int.normal, &ray.dir, &dir));
+        let cos = int.normal.dot(&dir).abs();
+        ray.color.mult(&f).mult(&radiance).scale(cos * weight / pdf)
+    }
+
+    //light of one of the punctual lights, picked uniformly, reaching the hit and scattered
+    //toward the camera. Scattering can't find them, so there is nothing to weight against
+    fn punctual_light(
+        &self,
+        ctx: &Context,
+        ray: &Ray,
+        int: &Intersection,
+        (u_choice, u_dir): (f64, (f64, f64)),
+    ) -> Vec3 {
+        let lights = &ctx.scene.punctual_lights;
+        let index = ((u_choice * lights.len() as f64) as usize).min(lights.len() - 1);
+        let (dir, distance, irradiance) = lights[index].sample(&int.pos, u_dir);
+        let f = bsdf(int.mat, &int.normal, &ray.dir, &dir);
+        if irradiance.norm() == 0.0 || f.norm() == 0.0 || !unoccluded(ctx, ray, int, &dir, distance)
+        {
+            return Vec3::ZERO;
+        }
+        let cos = int.normal.dot(&dir).abs();
+        ray.color
+            .mult(&f)
+            .mult(&irradiance)
+            .scale(cos * lights.len() as f64)
+    }
+}
+
+impl Integrator for PathTracer {
+    fn radiance<'a>(
+        &self,
+        ctx: &'a Context,
+        ray: Ray,
+        hit: Intersection<'a>,
+        sampler: &mut Sampler,
+        passes: &mut [Vec3],
+        _splats: &mut Vec<Splat>,
+    ) -> Vec3 {
+        self.trace(ctx, ray, hit, sampler, passes).0
+    }
+}
+
+//white where the hemisphere around the hit is free of geometry up to distance, black where it's
+//fully occluded
+pub struct AmbientOcclusion {
+    pub distance: f64,
+}
+
+impl Integrator for AmbientOcclusion {
+    fn radiance<'a>(
+        &self,
+        ctx: &'a Context,
+        ray: Ray,
+        hit: Intersection<'a>,
+        sampler: &mut Sampler,
+        _passes: &mut [Vec3],
+        _splats: &mut Vec<Splat>,
+    ) -> Vec3 {
+        if !hit.hit {
+            return Vec3::ZERO;
+        }
+        let normal = if hit.normal.dot(&ray.dir) > 0.0 {
+            hit.normal.scale(-1.0)
+        } else {
+            hit.normal
+        };
+        sampler.start_bounce(0);
+        let dir = Vec3::cosine_weighted_hemisphere(&normal, sampler.get_2d());
+        let mut occlusion_ray = Ray::new(&hit.pos + &normal.scale(0.001), dir);
+        occlusion_ray.time = ray.time;
+        let occluder = intersect(&occlusion_ray, &ctx.scene);
+        if occluder.hit && occluder.dist < self.distance {
+            Vec3::ZERO
+        } else {
+            Vec3::ONE
+        }
+    }
+}
+
+//normals remapped to 0 to 1
+pub struct Normals;
+
+impl Integrator for Normals {
+    fn radiance<'a>(
+        &self,
+        _ctx: &'a Context,
+        _ray: Ray,
+        hit: Intersection<'a>,
+        _sampler: &mut Sampler,
+        _passes: &mut [Vec3],
+        _splats: &mut Vec<Splat>,
+    ) -> Vec3 {
+        match hit.hit {
+            true => hit.normal.affine(-1.0, 1.0, 0.0, 1.0),
+            false => Vec3::ZERO,
+        }
+    }
+}
+
+pub struct Albedo;
+
+impl Integrator for Albedo {
+    fn radiance<'a>(
+        &self,
+        _ctx: &'a Context,
+        _ray: Ray,
+        hit: Intersection<'a>,
+        _sampler: &mut Sampler,
+        _passes: &mut [Vec3],
+        _splats: &mut Vec<Splat>,
+    ) -> Vec3 {
+        match hit.hit {
+            true => hit.mat.reflectance(),
+            false => Vec3::ZERO,
+        }
+    }
+}
+
+//white up close, fading to black far away
+pub struct Depth;
+
+impl Integrator for Depth {
+    fn radiance<'a>(
+        &self,
+        _ctx: &'a Context,
+        _ray: Ray,
+        hit: Intersection<'a>,
+        _sampler: &mut Sampler,
+        _passes: &mut [Vec3],
+        _splats: &mut Vec<Splat>,
+    ) -> Vec3 {
+        match hit.hit {
+            true => Vec3::ONE.scale(1.0 / (1.0 + hit.dist)),
+            false => Vec3::ZERO,
+        }
+    }
+}
+
+//bounces shown from blue (none) to red (MAX_SHOWN_BOUNCES or more)
+const MAX_SHOWN_BOUNCES: f64 = 16.0;
+
+//how long the paths of the path tracer are, to see where roulette and the hard cap stop them
+pub struct BounceCount {
+    pub path_tracer: PathTracer,
+}
+
+impl Integrator for BounceCount {
+    fn radiance<'a>(
+        &self,
+        ctx: &'a Context,
+        ray: Ray,
+        hit: Intersection<'a>,
+        sampler: &mut Sampler,
+        _passes: &mut [Vec3],
+        _splats: &mut Vec<Splat>,
+    ) -> Vec3 {
+        let (_, bounces) = self.path_tracer.trace(ctx, ray, hit, sampler, &mut []);
+        heat_color((bounces as f64 / MAX_SHOWN_BOUNCES).min(1.0))
+    }
+}
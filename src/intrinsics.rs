@@ -0,0 +1,70 @@
+use crate::Vec3;
+
+//Brown-Conrady coefficients, as given by usual calibration tools (k1 k2 p1 p2 k3)
+#[derive(Clone, Default)]
+pub struct Distortion {
+    pub k1: f64,
+    pub k2: f64,
+    pub k3: f64,
+    pub p1: f64,
+    pub p2: f64,
+}
+
+impl Distortion {
+    //distorts a point on the normalized image plane
+    pub fn distort(&self, x: f64, y: f64) -> (f64, f64) {
+        let r2 = x * x + y * y;
+        let radial = 1.0 + r2 * (self.k1 + r2 * (self.k2 + r2 * self.k3));
+        (
+            x * radial + 2.0 * self.p1 * x * y + self.p2 * (r2 + 2.0 * x * x),
+            y * radial + self.p1 * (r2 + 2.0 * y * y) + 2.0 * self.p2 * x * y,
+        )
+    }
+
+    //inverts distort with fixed point iterations, like calibration tools do
+    pub fn undistort(&self, x: f64, y: f64) -> (f64, f64) {
+        let (mut xu, mut yu) = (x, y);
+        for _ in 0..20 {
+            let (xd, yd) = self.distort(xu, yu);
+            let (dx, dy) = (x - xd, y - yd);
+            xu += dx;
+            yu += dy;
+            if dx.abs() + dy.abs() < 1e-12 {
+                break;
+            }
+        }
+        (xu, yu)
+    }
+}
+
+//pinhole intrinsics of a calibrated camera, in pixels of a width x height image. The calibration
+//convention is used : pixel centers at integer coordinates, v pointing down, z looking forward
+#[derive(Clone)]
+pub struct Intrinsics {
+    pub fx: f64,
+    pub fy: f64,
+    pub cx: f64,
+    pub cy: f64,
+    pub distortion: Distortion,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Intrinsics {
+    //camera space direction (x right, y up, looking at -z) of the ray through x, y in the -1 to 1
+    //window, so that pixel (i, j) of the render samples pixel (i, j) of the calibrated image
+    pub fn ray_dir(&self, x: f64, y: f64) -> Vec3 {
+        let u = (x + 1.0) / 2.0 * (self.width as f64) - 0.5;
+        let v = (1.0 - y) / 2.0 * (self.height as f64) - 0.5;
+        let (xu, yu) = self
+            .distortion
+            .undistort((u - self.cx) / self.fx, (v - self.cy) / self.fy);
+
+        Vec3 {
+            x: xu,
+            y: -yu,
+            z: -1.0,
+        }
+        .normalized()
+    }
+}
@@ -0,0 +1,327 @@
+use std::f64::consts::PI;
+use std::fs;
+use std::io;
+
+use crate::Vec3;
+
+//lens prescriptions are in millimeters, scenes in meters
+pub const MM_TO_SCENE: f64 = 0.001;
+
+//wavelengths (in nm) standing for the red, green and blue channels when a lens disperses light
+const CHANNEL_WAVELENGTHS: [f64; 3] = [630.0, 532.0, 465.0];
+//Fraunhofer F, d and C lines, which define the abbe number
+const LAMBDA_F: f64 = 486.1;
+const LAMBDA_D: f64 = 587.6;
+const LAMBDA_C: f64 = 656.3;
+
+//one spherical interface of the lens, with the medium behind it (towards the film)
+pub struct LensElement {
+    pub radius: f64,    //curvature radius, 0 for the aperture stop
+    pub thickness: f64, //distance to the next interface (to the film for the last one)
+    pub ior: f64,       //index at the d line, 0 or 1 for air
+    pub abbe: f64,      //abbe number of the medium, 0 for no dispersion
+    pub aperture_radius: f64,
+}
+
+impl LensElement {
+    fn is_stop(&self) -> bool {
+        self.radius == 0.0
+    }
+
+    //cauchy's equation fitted through nd and the abbe number
+    fn ior_at(&self, wavelength: Option<f64>) -> f64 {
+        if self.ior == 0.0 {
+            return 1.0;
+        }
+        match wavelength {
+            Some(lambda) if self.abbe > 0.0 => {
+                let b = (self.ior - 1.0) / self.abbe / (LAMBDA_F.powi(-2) - LAMBDA_C.powi(-2));
+                self.ior + b * (lambda.powi(-2) - LAMBDA_D.powi(-2))
+            }
+            _ => self.ior,
+        }
+    }
+
+    //where a ray crosses this interface (whose vertex is at element_z) and the normal there,
+    //facing against the ray. None if the ray misses it or is blocked by the aperture
+    fn hit(&self, element_z: f64, origin: &Vec3, dir: &Vec3) -> Option<(Vec3, Option<Vec3>)> {
+        let (hit, normal) = if self.is_stop() {
+            let t = (element_z - origin.z) / dir.z;
+            (origin + &dir.scale(t), None)
+        } else {
+            let z_center = element_z + self.radius;
+            let t = intersect_element(self.radius, z_center, origin, dir)?;
+            let hit = origin + &dir.scale(t);
+            let normal = Vec3 {
+                z: hit.z - z_center,
+                ..hit.clone()
+            }
+            .normalized();
+            (hit, Some(normal.scale(-normal.dot(dir).signum())))
+        };
+
+        if hit.x * hit.x + hit.y * hit.y > self.aperture_radius * self.aperture_radius {
+            None
+        } else {
+            Some((hit, normal))
+        }
+    }
+}
+
+//a stack of lens elements in front of the film, from the front (scene side) to the back.
+//lens space has the film at z = 0 and the lens towards -z, which is also the camera's forward
+pub struct LensSystem {
+    pub elements: Vec<LensElement>,
+    pub sensor_width: f64, //in mm, covering x from -1 to 1 (and y the same way)
+}
+
+fn intersect_element(radius: f64, z_center: f64, origin: &Vec3, dir: &Vec3) -> Option<f64> {
+    let o = Vec3 {
+        z: origin.z - z_center,
+        ..origin.clone()
+    };
+    let a = dir.dot(dir);
+    let b = 2.0 * dir.dot(&o);
+    let c = o.dot(&o) - radius * radius;
+    let discr = b * b - 4.0 * a * c;
+    if discr < 0.0 {
+        return None;
+    }
+    let t0 = (-b - discr.sqrt()) / (2.0 * a);
+    let t1 = (-b + discr.sqrt()) / (2.0 * a);
+    let use_closer = (dir.z > 0.0) ^ (radius < 0.0);
+    let t = if use_closer { t0.min(t1) } else { t0.max(t1) };
+    if t < 0.0 {
+        None
+    } else {
+        Some(t)
+    }
+}
+
+//snell's law, normal facing against dir, eta = n1 / n2. None on total internal reflection
+fn refract(dir: &Vec3, normal: &Vec3, eta: f64) -> Option<Vec3> {
+    let cos_i = -normal.dot(dir);
+    let sin2_t = eta * eta * (1.0 - cos_i * cos_i).max(0.0);
+    if sin2_t > 1.0 {
+        return None;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    Some(&dir.scale(eta) + &normal.scale(eta * cos_i - cos_t))
+}
+
+impl LensSystem {
+    //reads a prescription with one interface per line : radius thickness ior aperture [abbe],
+    //in mm, from the front element to the back, the aperture being a diameter. '#' starts a comment
+    pub fn load(path: &str, sensor_width: f64) -> io::Result<LensSystem> {
+        let text = fs::read_to_string(path)?;
+        let mut elements = vec![];
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let values = line
+                .split_whitespace()
+                .map(|v| v.parse::<f64>())
+                .collect::<Result<Vec<f64>, _>>()
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            if values.len() < 4 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("lens element needs at least 4 values : {}", line),
+                ));
+            }
+            elements.push(LensElement {
+                radius: values[0],
+                thickness: values[1],
+                ior: values[2],
+                abbe: values.get(4).cloned().unwrap_or(0.0),
+                aperture_radius: values[3] / 2.0,
+            });
+        }
+        if elements.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "empty lens prescription",
+            ));
+        }
+
+        Ok(LensSystem {
+            elements,
+            sensor_width,
+        })
+    }
+
+    pub fn is_dispersive(&self) -> bool {
+        self.elements.iter().any(|e| e.abbe > 0.0 && e.ior > 1.0)
+    }
+
+    fn rear_z(&self) -> f64 {
+        -self.elements.last().unwrap().thickness
+    }
+
+    fn front_z(&self) -> f64 {
+        -self.elements.iter().map(|e| e.thickness).sum::<f64>()
+    }
+
+    //traces a lens space ray from the film out of the front element. None if it gets blocked
+    fn trace_from_film(
+        &self,
+        origin: &Vec3,
+        dir: &Vec3,
+        wavelength: Option<f64>,
+    ) -> Option<(Vec3, Vec3)> {
+        let mut origin = origin.clone();
+        let mut dir = dir.clone();
+        let mut element_z = 0.0;
+
+        for (i, element) in self.elements.iter().enumerate().rev() {
+            element_z -= element.thickness;
+            let (hit, normal) = element.hit(element_z, &origin, &dir)?;
+            origin = hit;
+
+            if let Some(normal) = normal {
+                let eta_i = element.ior_at(wavelength);
+                let eta_t = if i > 0 {
+                    self.elements[i - 1].ior_at(wavelength)
+                } else {
+                    1.0
+                };
+                dir = refract(&dir.normalized(), &normal, eta_i / eta_t)?;
+            }
+        }
+
+        Some((origin, dir.normalized()))
+    }
+
+    //traces a lens space ray from the scene through the lens towards the film
+    fn trace_from_scene(&self, origin: &Vec3, dir: &Vec3) -> Option<(Vec3, Vec3)> {
+        let mut origin = origin.clone();
+        let mut dir = dir.clone();
+        let mut element_z = self.front_z();
+
+        for (i, element) in self.elements.iter().enumerate() {
+            let (hit, normal) = element.hit(element_z, &origin, &dir)?;
+            origin = hit;
+
+            if let Some(normal) = normal {
+                let eta_i = if i > 0 {
+                    self.elements[i - 1].ior_at(None)
+                } else {
+                    1.0
+                };
+                let eta_t = element.ior_at(None);
+                dir = refract(&dir.normalized(), &normal, eta_i / eta_t)?;
+            }
+            element_z += element.thickness;
+        }
+
+        Some((origin, dir.normalized()))
+    }
+
+    //z of the principal plane and of the focal point, given a ray parallel to the axis and what
+    //comes out of the lens
+    fn cardinal_points(r_in: &Vec3, r_out: &(Vec3, Vec3)) -> (f64, f64) {
+        let (origin, dir) = r_out;
+        let tf = -origin.x / dir.x;
+        let tp = (r_in.x - origin.x) / dir.x;
+        (origin.z + tp * dir.z, origin.z + tf * dir.z)
+    }
+
+    //moves the film (the thickness of the last element) so that objects at focus_distance (in mm,
+    //from the film) are sharp, using the thick lens approximation
+    pub fn focus(&mut self, focus_distance: f64) {
+        let x = 0.001 * self.sensor_width;
+
+        let scene_origin = Vec3 {
+            x,
+            y: 0.0,
+            z: self.front_z() - 1.0,
+        };
+        let film_origin = Vec3 {
+            x,
+            y: 0.0,
+            z: self.rear_z() + 1.0,
+        };
+        let forward = Vec3 {
+            z: -1.0,
+            ..Vec3::ZERO
+        };
+        let backward = forward.scale(-1.0);
+
+        let (from_scene, from_film) = match (
+            self.trace_from_scene(&scene_origin, &backward),
+            self.trace_from_film(&film_origin, &forward, None),
+        ) {
+            (Some(from_scene), Some(from_film)) => (from_scene, from_film),
+            _ => {
+                println!(
+                    "WARNING : paraxial ray blocked by the lens, keeping the film where it is"
+                );
+                return;
+            }
+        };
+        let (pz_0, fz_0) = LensSystem::cardinal_points(&scene_origin, &from_scene);
+        let (pz_1, _) = LensSystem::cardinal_points(&film_origin, &from_film);
+
+        let f = fz_0 - pz_0;
+        let z = -focus_distance;
+        let c = (pz_1 - z - pz_0) * (pz_1 - z - 4.0 * f - pz_0);
+        if c <= 0.0 {
+            println!(
+                "WARNING : focus distance too short for the lens, keeping the film where it is"
+            );
+            return;
+        }
+        let delta = 0.5 * (pz_1 - z + pz_0 - c.sqrt());
+
+        self.elements.last_mut().unwrap().thickness += delta;
+    }
+
+    //samples a lens space ray leaving the front element for the film point seen at x, y (in the
+    //-1 to 1 window), along with its weight, accounting for the cos^4 falloff and dispersion.
+    //u_pupil picks the point of the rear element, u_channel the wavelength
+    pub fn sample_ray(
+        &self,
+        x: f64,
+        y: f64,
+        u_pupil: (f64, f64),
+        u_channel: f64,
+    ) -> Option<(Vec3, Vec3, Vec3)> {
+        //the lens flips the image, so we look at the opposite side of the film
+        let half_width = self.sensor_width / 2.0;
+        let film_point = Vec3 {
+            x: -x * half_width,
+            y: -y * half_width,
+            z: 0.0,
+        };
+
+        let rear = self.elements.last().unwrap();
+        let r = rear.aperture_radius * u_pupil.0.sqrt();
+        let phi = 2.0 * PI * u_pupil.1;
+        let pupil_point = Vec3 {
+            x: r * phi.cos(),
+            y: r * phi.sin(),
+            z: self.rear_z(),
+        };
+        let dir = pupil_point.minus(&film_point).normalized();
+        let cos_theta = -dir.z;
+
+        let (wavelength, mut weight) = if self.is_dispersive() {
+            let channel = ((u_channel * 3.0) as usize).min(2);
+            let mut weight = Vec3::ZERO;
+            match channel {
+                0 => weight.x = 3.0,
+                1 => weight.y = 3.0,
+                _ => weight.z = 3.0,
+            }
+            (Some(CHANNEL_WAVELENGTHS[channel]), weight)
+        } else {
+            (None, Vec3::ONE)
+        };
+        weight = weight.scale(cos_theta.powi(4));
+
+        let (origin, dir) = self.trace_from_film(&film_point, &dir, wavelength)?;
+        Some((origin.scale(MM_TO_SCENE), dir, weight))
+    }
+}
@@ -0,0 +1,87 @@
+use std::f64::consts::PI;
+
+use crate::scene::Scene;
+use crate::tonemap::luminance;
+use crate::vector::Vec3;
+
+//the emissive primitives light paths can start from, picked in proportion to their power
+pub struct Lights {
+    cdf: Vec<(usize, f64)>, //objects that can be sampled and their cumulated probabilities
+    pdf_pos: Vec<f64>,      //area density of the starting points on each object
+}
+
+//a point of a light and a direction it emits along
+pub struct Emission {
+    pub object: usize,
+    pub pos: Vec3,
+    pub normal: Vec3,
+    pub dir: Vec3,
+    pub pdf_pos: f64,
+    pub pdf_dir: f64,
+}
+
+impl Lights {
+    pub fn new(scene: &Scene) -> Lights {
+        let powers: Vec<(usize, f64)> = scene
+            .objects
+            .iter()
+            .enumerate()
+            .filter_map(|(index, object)| {
+                let power = luminance(&object.material().emissive) * object.area()?;
+                (power > 0.0).then_some((index, power))
+            })
+            .collect();
+        let total: f64 = powers.iter().map(|(_, power)| power).sum();
+
+        let mut pdf_pos = vec![0.0; scene.objects.len()];
+        let mut cumulated = 0.0;
+        let mut cdf = vec![];
+        for (index, power) in powers {
+            let prob = power / total;
+            pdf_pos[index] = prob / scene.objects[index].area().unwrap();
+            cumulated += prob;
+            cdf.push((index, cumulated));
+        }
+        Lights { cdf, pdf_pos }
+    }
+
+    //0 for the objects that can't be sampled
+    pub fn pdf_pos(&self, object: usize) -> f64 {
+        self.pdf_pos[object]
+    }
+
+    //density of the direction dir leaving a light of the given normal
+    pub fn pdf_dir(normal: &Vec3, dir: &Vec3) -> f64 {
+        normal.dot(dir).abs() / (2.0 * PI)
+    }
+
+    //both sides of a light emit, the direction being cosine distributed on one of them
+    pub fn sample(
+        &self,
+        scene: &Scene,
+        u_light: f64,
+        u_pos: (f64, f64),
+        u_dir: (f64, f64),
+    ) -> Option<Emission> {
+        let &(object, _) = self
+            .cdf
+            .iter()
+            .find(|(_, cdf)| u_light < *cdf)
+            .or(self.cdf.last())?;
+        let (pos, normal) = scene.objects[object].sample_surface(u_pos)?;
+        let (side, u0) = if u_dir.0 < 0.5 {
+            (1.0, 2.0 * u_dir.0)
+        } else {
+            (-1.0, 2.0 * u_dir.0 - 1.0)
+        };
+        let dir = Vec3::cosine_weighted_hemisphere(&normal.scale(side), (u0, u_dir.1));
+        Some(Emission {
+            object,
+            pdf_pos: self.pdf_pos[object],
+            pdf_dir: Lights::pdf_dir(&normal, &dir),
+            pos,
+            normal,
+            dir,
+        })
+    }
+}
@@ -1,21 +1,50 @@
+mod accumulator;
+mod aov;
+mod bdpt;
+mod bluenoise;
+mod bsdf;
+mod checkpoint;
+mod denoise;
+mod environment;
+mod film;
+mod framebuffer;
+mod integrator;
+mod intrinsics;
+mod lens;
+mod lights;
 mod material;
+mod mlt;
+mod motion;
+mod passes;
+mod punctual;
 mod render;
+mod sampler;
 mod scene;
+mod scheduler;
+mod settings;
+mod sky;
+mod sppm;
+mod stereo;
+mod tonemap;
 mod vector;
 
-use std::path::Path;
+use std::io;
 use std::sync::Arc;
-use std::sync::Mutex;
-use std::thread;
-
-use image::ImageBuffer;
+use std::time::Instant;
 
+use accumulator::Accumulator;
+use aov::{Aov, AovBuffers};
+use checkpoint::Checkpoint;
+use denoise::Denoiser;
+use framebuffer::{extension, sibling_output, write_exr, FrameBuffer, Output};
 use material::Material;
-use render::{pixel_shader, Camera, Context};
-use scene::Scene;
-use vector::{Mat4, Vec3};
+use passes::{Pass, PassBuffers};
+use render::{Camera, Context};
+use sampler::Sampler;
+use settings::Settings;
+use vector::Vec3;
 
-fn tests() {
+fn _tests() {
     let normal = Vec3 {
         x: 0.0,
         y: 0.0,
@@ -38,11 +67,37 @@ fn tests() {
     println!("rotated : {}, dot: {}, acos: {}", rotated, dot, acos)
 }
 
+//passes and aovs go in the same file for exr, next to the render otherwise
+fn save_render(
+    img: &FrameBuffer,
+    passes: &PassBuffers,
+    aovs: &AovBuffers,
+    output: &Output,
+) -> io::Result<()> {
+    if extension(&output.path) == "exr" {
+        let mut channels = img.developed(output).exr_channels("", &["R", "G", "B"]);
+        channels.extend(passes.exr_channels(output));
+        channels.extend(aovs.exr_channels());
+        write_exr(
+            &output.path,
+            img.width,
+            img.height,
+            channels,
+            output.exr_half,
+        )
+    } else {
+        img.save(output)?;
+        passes.save_files(output)?;
+        aovs.save_files(output)
+    }
+}
+
 fn main() {
-    //tests();
+    //_tests();
     //return;
 
-    let (width, height): (u32, u32) = (1000, 1000);
+    let settings = Settings::from_args();
+    let (width, height) = (settings.width, settings.height);
 
     let cam_pos = Vec3 {
         x: -0.5,
@@ -56,65 +111,160 @@ fn main() {
         z: 0.2,
     };
 
+    let mut camera = match &settings.extrinsic {
+        Some(extrinsic) => Camera::from_extrinsic(extrinsic, 0.7),
+        None => Camera::new(cam_pos, cam_target.clone(), 0.7),
+    };
+    camera.aspect = width as f64 / height as f64;
+    camera.intrinsics = settings.intrinsics;
+    camera.motion = settings.camera_motion;
+    if let Some(lens) = settings.lens {
+        //focus on the target unless told otherwise
+        let focus_distance = settings
+            .focus_distance
+            .unwrap_or_else(|| cam_target.minus(&camera.pos).norm());
+        camera.lens = Some(Arc::new(lens));
+        camera.focus(focus_distance);
+    }
+
+    let scene_hash = settings.scene.hash();
+    //every pass is a set of strata, whose size is kept when resuming so that the samples don't
+    //depend on the spp or pass_spp a render is resumed with
+    let strata = settings
+        .resume
+        .as_ref()
+        .map_or(settings.pass_spp, |checkpoint| checkpoint.strata);
     let ctx = Context {
-        scene: Scene::cornell_box(),
+        scene: settings.scene,
         width,
         height,
-        camera: Camera::new(cam_pos, cam_target, 0.7),
+        camera,
+        stereo: settings.stereo,
+        film: settings.film,
+        passes: settings.passes.clone(),
+        sampler: Sampler::new(settings.sampler, settings.seed, strata),
+        integrator: settings.integrator,
+        shutter_interval: settings.shutter_interval,
     };
 
-    let img: image::RgbImage = ImageBuffer::new(width, height);
-
-    let img_ref = Arc::new(Mutex::new(img));
-    //let ctx_ref = Arc::new(ctx);
-
-    let n_threads = 8;
-
-    let loading_counter = Arc::new(Mutex::new(0)); //we increment for every column. percentage is this counter over the width.
+    //checkpoints keep the aovs, should they be asked for when resuming
+    let keep_aovs =
+        !settings.aovs.is_empty() || settings.denoiser.is_some() || settings.checkpoint.is_some();
+    let mut acc = match settings.resume {
+        Some(checkpoint) => {
+            if checkpoint.scene_hash != scene_hash {
+                panic!("the scene changed since the checkpoint was saved, refusing to resume");
+            }
+            checkpoint.acc
+        }
+        None => Accumulator::new(width, height, settings.passes.len(), keep_aovs),
+    };
+    let save = |acc: &Accumulator| {
+        save_all(
+            acc,
+            &settings.outputs,
+            &settings.passes,
+            &settings.aovs,
+            settings.denoiser.as_ref(),
+        );
+        if let Some(path) = &settings.checkpoint {
+            if let Err(e) = Checkpoint::save(path, &settings.args, scene_hash, strata, acc) {
+                println!("WARNING : Couldn't save checkpoint at {} ({})", path, e);
+            }
+        }
+    };
 
-    thread::scope(|s| {
-        let mut threads = vec![];
+    //passes of pass_spp samples over the image until every pixel has spp samples (or is
+    //converged, with adaptive sampling) or the time budget is spent, saving the current estimate
+    //along the way
+    let max_spp = match settings.time_budget {
+        Some(_) => u32::MAX,
+        None => settings.samples_per_pixel,
+    };
+    let start = Instant::now();
+    let mut last_save = start;
+    let mut passes_since_save = 0;
+    loop {
+        let spp = acc.next_pass(max_spp, settings.pass_spp, settings.adaptive.as_ref());
+        let active = spp.iter().filter(|s| **s > 0).count();
+        if active == 0 {
+            break;
+        }
+        settings.scheduler.render_pass(&ctx, &mut acc, &spp);
+        passes_since_save += 1;
 
-        for t in 0..n_threads {
-            //let img_clone = Arc::clone(&img_ref);
-            //let ctx_clone = Arc::clone(&ctx_ref);
-            //let counter_clone = Arc::clone(&loading_counter);
-            //we go from t * width / n_threads to (t+1) * width / n_threads
-            let low = t * width / n_threads;
-            let up = (t + 1) * width / n_threads;
+        let elapsed = start.elapsed().as_secs_f64();
+        let max_done = acc.samples.iter().cloned().max().unwrap_or(0);
+        match settings.time_budget {
+            Some(budget) => println!(
+                "{:.1}% ({} spp max, {} pixels sampled, {:.1}s)",
+                100.0 * (elapsed / budget).min(1.0),
+                max_done,
+                active,
+                elapsed
+            ),
+            None => println!(
+                "{} spp max, {} pixels sampled in {:.1}s",
+                max_done, active, elapsed
+            ),
+        }
+        if settings.time_budget.is_some_and(|budget| elapsed >= budget) {
+            break;
+        }
 
-            let ctx_ref = &ctx;
-            let img_ref = &img_ref;
-            let counter_ref = &loading_counter;
+        let save_due = settings
+            .save_interval
+            .is_some_and(|interval| last_save.elapsed().as_secs_f64() >= interval)
+            || settings.save_passes.is_some_and(|n| passes_since_save >= n);
+        if save_due {
+            save(&acc);
+            last_save = Instant::now();
+            passes_since_save = 0;
+        }
+    }
 
-            let new_thread = s.spawn(move || {
-                for i in low..up {
-                    for j in 0..height {
-                        let pixel = pixel_shader(ctx_ref, i, j, 7, 2000);
-                        img_ref.lock().unwrap().put_pixel(i, j, pixel);
-                    }
-                    let mut ref_ = counter_ref.lock().unwrap();
+    save(&acc);
+}
 
-                    *ref_ += 1;
-                    println!("{}%", 100.0 * (*ref_ as f32) / (width as f32));
-                }
-            });
-            threads.push(new_thread);
-        }
-        for t in threads {
-            t.join().unwrap();
-        }
+//writes the current estimate to every output, falling back to ./buffer_render when it can't
+fn save_all(
+    acc: &Accumulator,
+    outputs: &[Output],
+    passes: &[Pass],
+    aovs: &[Aov],
+    denoiser: Option<&Denoiser>,
+) {
+    let raw = acc.image();
+    let denoised = denoiser.map(|denoiser| {
+        //first hit albedo and normals guide the denoiser
+        let guides = acc.aov_buffers(&[Aov::Albedo, Aov::Normal]);
+        denoiser.denoise(&raw, &guides.buffers[0].1, &guides.buffers[1].1)
     });
-
-    let img = img_ref
-        .lock()
-        .expect("couldn't acquire image after render :(");
-    match img.save(Path::new("./renders/render.png")) {
-        Ok(_) => (),
-        Err(_) => {
-            println!("WARNING : Couldn't save render at desired path, trying to save it at ./buffer_render.png");
-            img.save("./buffer_render.png")
+    let img = denoised.as_ref().unwrap_or(&raw);
+    let passes = acc.pass_buffers(passes);
+    let aovs = acc.aov_buffers(aovs);
+    let save = |output: &Output| {
+        if denoiser.is_some_and(|d| d.keep_raw) {
+            raw.save(&sibling_output(output, "raw"))?;
+        }
+        save_render(img, &passes, &aovs, output)
+    };
+    for output in outputs {
+        match save(output) {
+            Ok(_) => (),
+            Err(e) => {
+                let extension = output.path.rsplit('.').next().unwrap_or("png");
+                let buffer_path = format!("./buffer_render.{}", extension);
+                println!(
+                    "WARNING : Couldn't save render at {} ({}), trying to save it at {}",
+                    output.path, e, buffer_path
+                );
+                save(&Output {
+                    path: buffer_path,
+                    ..output.clone()
+                })
                 .expect("DISK ERROR! IMPORTANT! WHY!");
+            }
         }
     }
 }
@@ -89,4 +89,9 @@ impl Material {
     pub fn default() -> &'static Material {
         &Material::DEFAULT_MAT
     }
+
+    //average reflectance, mixing the specular and diffuse lobes like pixel_shader does
+    pub fn reflectance(&self) -> Vec3 {
+        &self.specular.scale(self.specularity) + &self.albedo.scale(1.0 - self.specularity)
+    }
 }
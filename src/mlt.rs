@@ -0,0 +1,183 @@
+use std::sync::OnceLock;
+
+use crate::integrator::{Integrator, PathTracer, Splat};
+use crate::render::{camera_ray, falloff_cos, intersect, Context, Intersection, Ray};
+use crate::sampler::{Sampler, BOUNCE_DIMENSIONS, CAMERA_DIMENSIONS};
+use crate::tonemap::luminance;
+use crate::vector::Vec3;
+
+//probability of a mutation drawing all the numbers anew rather than perturbing them
+const LARGE_STEP: f64 = 0.3;
+//smallest and largest perturbation of a small step, from Kelemen et al.
+const SMALLEST_STEP: f64 = 1.0 / 1024.0;
+const LARGEST_STEP: f64 = 1.0 / 64.0;
+//set of the global samples the bootstrap paths are drawn from
+const BOOTSTRAP_SET: u32 = u32::MAX;
+
+//primary sample space metropolis light transport (Kelemen et al.) : the numbers a path of the
+//path tracer is made from, the first two of them picking the pixel, are mutated by a Markov chain
+//that visits them in proportion to the luminance of the path, so that bright but hard to find
+//paths get explored once found. Each sample of a pixel runs a chain of mutations started from a
+//path resampled from bootstrap paths, which also give the mean luminance the image is scaled by.
+//Every mutation is splatted to the pixels of both the proposed and the current path, weighted
+//by the probability of accepting it
+pub struct Mlt {
+    path_tracer: PathTracer,
+    mutations: u32,    //per sample
+    bootstrap: u32,    //paths
+    dimensions: usize, //numbers of a path
+    normalization: OnceLock<Bootstrap>,
+}
+
+struct Bootstrap {
+    mean: f64,           //luminance of the paths
+    cumulated: Vec<f64>, //luminance of the paths, to resample them
+}
+
+//a path, the pixel it is seen in and what it brings there
+struct State {
+    numbers: Vec<f64>,
+    pixel: (u32, u32),
+    color: Vec3, //developed
+    luminance: f64,
+}
+
+impl Mlt {
+    pub fn new(path_tracer: PathTracer, mutations: u32, bootstrap: u32) -> Mlt {
+        let bounces = path_tracer.bounces as u32 + 1;
+        Mlt {
+            dimensions: 2 + (CAMERA_DIMENSIONS + bounces * BOUNCE_DIMENSIONS) as usize,
+            path_tracer,
+            mutations,
+            bootstrap,
+            normalization: OnceLock::new(),
+        }
+    }
+
+    fn state(&self, ctx: &Context, numbers: Vec<f64>) -> State {
+        let pixel = (
+            ((numbers[0] * ctx.width as f64) as u32).min(ctx.width - 1),
+            ((numbers[1] * ctx.height as f64) as u32).min(ctx.height - 1),
+        );
+        let mut sampler = ctx.sampler.replaying(&numbers[2..]);
+        let color = match camera_ray(ctx, pixel.0, pixel.1, &mut sampler) {
+            Some(ray) => {
+                let cos = falloff_cos(ctx, &ray);
+                let hit = intersect(&ray, &ctx.scene);
+                let (radiance, _) = self.path_tracer.trace(ctx, ray, hit, &mut sampler, &mut []);
+                ctx.film.develop(&radiance, cos)
+            }
+            None => Vec3::ZERO, //blocked by the lens
+        };
+        let luminance = luminance(&color);
+        State {
+            numbers,
+            pixel,
+            luminance: if luminance.is_finite() {
+                luminance
+            } else {
+                0.0
+            },
+            color,
+        }
+    }
+
+    fn bootstrap_numbers(&self, ctx: &Context, path: u32) -> Vec<f64> {
+        let mut sampler = ctx.sampler.clone();
+        sampler.start_global_sample(BOOTSTRAP_SET, path);
+        (0..self.dimensions).map(|_| sampler.get_1d()).collect()
+    }
+
+    fn bootstrap(&self, ctx: &Context) -> &Bootstrap {
+        self.normalization.get_or_init(|| {
+            let mut total = 0.0;
+            let cumulated = (0..self.bootstrap)
+                .map(|path| {
+                    total += self.state(ctx, self.bootstrap_numbers(ctx, path)).luminance;
+                    total
+                })
+                .collect();
+            Bootstrap {
+                mean: total / self.bootstrap.max(1) as f64,
+                cumulated,
+            }
+        })
+    }
+
+    //the numbers of a mutation of a path, the random ones being drawn from sampler
+    fn mutate(&self, numbers: &[f64], sampler: &mut Sampler) -> Vec<f64> {
+        if sampler.get_1d() < LARGE_STEP {
+            return numbers.iter().map(|_| sampler.get_1d()).collect();
+        }
+        numbers
+            .iter()
+            .map(|number| {
+                let (u_size, u_sign) = sampler.get_2d();
+                let step = LARGEST_STEP * (-(LARGEST_STEP / SMALLEST_STEP).ln() * u_size).exp();
+                let moved = if u_sign < 0.5 {
+                    number + step
+                } else {
+                    number - step
+                };
+                moved - moved.floor()
+            })
+            .collect()
+    }
+}
+
+//the contribution of state to its pixel, with the given weight. Chains often stay in a pixel, so
+//splats following each other there are merged
+fn splat(state: &State, weight: f64, splats: &mut Vec<Splat>) {
+    if weight <= 0.0 || state.luminance == 0.0 {
+        return;
+    }
+    let color = state.color.scale(weight / state.luminance);
+    match splats.last_mut() {
+        Some(last) if (last.x, last.y) == state.pixel => last.color = &last.color + &color,
+        _ => splats.push(Splat {
+            x: state.pixel.0,
+            y: state.pixel.1,
+            color,
+        }),
+    }
+}
+
+impl Integrator for Mlt {
+    fn radiance<'a>(
+        &self,
+        ctx: &'a Context,
+        _ray: Ray,
+        _hit: Intersection<'a>,
+        sampler: &mut Sampler,
+        _passes: &mut [Vec3],
+        splats: &mut Vec<Splat>,
+    ) -> Vec3 {
+        let bootstrap = self.bootstrap(ctx);
+        if bootstrap.mean == 0.0 {
+            return Vec3::ZERO;
+        }
+        sampler.start_bounce(0);
+        let total = bootstrap.cumulated.last().unwrap();
+        let u = sampler.get_1d() * total;
+        let path = bootstrap.cumulated.partition_point(|&c| c <= u) as u32;
+        let mut current = self.state(ctx, self.bootstrap_numbers(ctx, path));
+
+        //every mutation brings mean / mutations of luminance, the accumulator dividing the splats
+        //by the samples per pixel
+        let scale = bootstrap.mean / self.mutations as f64;
+        for _ in 0..self.mutations {
+            let proposed = self.state(ctx, self.mutate(&current.numbers, sampler));
+            let accept = if current.luminance > 0.0 {
+                (proposed.luminance / current.luminance).min(1.0)
+            } else {
+                1.0
+            };
+            splat(&proposed, accept * scale, splats);
+            splat(&current, (1.0 - accept) * scale, splats);
+            if sampler.get_1d() < accept {
+                current = proposed;
+            }
+        }
+        Vec3::ZERO
+    }
+}
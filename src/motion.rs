@@ -0,0 +1,59 @@
+use crate::material::Material;
+use crate::render::{Intersection, Primitive, Ray};
+use crate::Vec3;
+
+#[derive(Clone, Debug)]
+pub struct Keyframe {
+    pub time: f64,
+    pub offset: Vec3,
+}
+
+//a translation over time
+#[derive(Clone, Debug)]
+pub enum Motion {
+    Linear(Vec3),             //velocity, the offset is zero at time 0
+    Keyframed(Vec<Keyframe>), //sorted by time, linearly interpolated and held outside
+}
+
+impl Motion {
+    pub fn offset(&self, time: f64) -> Vec3 {
+        match self {
+            Motion::Linear(velocity) => velocity.scale(time),
+            Motion::Keyframed(keys) => {
+                let next = keys.iter().position(|k| k.time > time);
+                match next {
+                    None => keys.last().map_or(Vec3::ZERO, |k| k.offset.clone()),
+                    Some(0) => keys[0].offset.clone(),
+                    Some(n) => {
+                        let (k0, k1) = (&keys[n - 1], &keys[n]);
+                        let t = (time - k0.time) / (k1.time - k0.time);
+                        &k0.offset.scale(1.0 - t) + &k1.offset.scale(t)
+                    }
+                }
+            }
+        }
+    }
+}
+
+//a primitive moving with the given motion, intersected at the time of the ray
+#[derive(Debug)]
+pub struct Moving {
+    pub object: Box<dyn Primitive + Send + Sync>,
+    pub motion: Motion,
+}
+
+impl Primitive for Moving {
+    fn intersect(&self, ray: &Ray) -> Intersection<'_> {
+        let offset = self.motion.offset(ray.time);
+        let mut local_ray = Ray::new(ray.origin.minus(&offset), ray.dir.clone());
+        local_ray.time = ray.time;
+
+        let mut inter = self.object.intersect(&local_ray);
+        inter.pos = &inter.pos + &offset;
+        inter
+    }
+
+    fn material(&self) -> &Material {
+        self.object.material()
+    }
+}
@@ -0,0 +1,190 @@
+use std::io;
+
+use crate::framebuffer::{sibling_output, FrameBuffer, Output};
+use crate::Vec3;
+
+//events of a light path, from the camera to the emitter
+pub const CAMERA: u8 = b'C';
+pub const DIFFUSE: u8 = b'D';
+pub const SPECULAR: u8 = b'S'; //mirror reflections, be it the specular lobe or a smooth material
+pub const TRANSMISSION: u8 = b'T';
+pub const EMISSION: u8 = b'L';
+
+const EVENTS: &[u8] = &[CAMERA, DIFFUSE, SPECULAR, TRANSMISSION, EMISSION];
+
+#[derive(Clone, Copy)]
+enum Repeat {
+    Once,
+    Optional,    //?
+    Any,         //*
+    AtLeastOnce, //+
+}
+
+//a light path expression : a regular expression over the events of a path, like CD.+L for
+//indirect diffuse light. Supports . for any event, [DS] sets, the ? * + quantifiers and |
+//alternatives
+#[derive(Clone)]
+pub struct Lpe {
+    alternatives: Vec<Vec<(Vec<u8>, Repeat)>>,
+}
+
+impl Lpe {
+    pub fn parse(expression: &str) -> Option<Lpe> {
+        let mut alternatives = vec![];
+        for alternative in expression.split('|') {
+            let mut atoms: Vec<(Vec<u8>, Repeat)> = vec![];
+            let mut chars = alternative.bytes();
+            while let Some(c) = chars.next() {
+                let repeat = match c {
+                    b'?' => Some(Repeat::Optional),
+                    b'*' => Some(Repeat::Any),
+                    b'+' => Some(Repeat::AtLeastOnce),
+                    _ => None,
+                };
+                if let Some(repeat) = repeat {
+                    match atoms.last_mut() {
+                        Some(atom) if matches!(atom.1, Repeat::Once) => atom.1 = repeat,
+                        _ => return None,
+                    }
+                    continue;
+                }
+                let set = match c {
+                    b'.' => EVENTS.to_vec(),
+                    b'[' => {
+                        let mut set = vec![];
+                        loop {
+                            match chars.next()? {
+                                b']' => break,
+                                c if EVENTS.contains(&c) => set.push(c),
+                                _ => return None,
+                            }
+                        }
+                        if set.is_empty() {
+                            return None;
+                        }
+                        set
+                    }
+                    c if EVENTS.contains(&c) => vec![c],
+                    _ => return None,
+                };
+                atoms.push((set, Repeat::Once));
+            }
+            alternatives.push(atoms);
+        }
+        Some(Lpe { alternatives })
+    }
+
+    pub fn matches(&self, path: &[u8]) -> bool {
+        self.alternatives
+            .iter()
+            .any(|atoms| Lpe::matches_atoms(atoms, path))
+    }
+
+    //backtracking, paths being a few events long
+    fn matches_atoms(atoms: &[(Vec<u8>, Repeat)], path: &[u8]) -> bool {
+        let ((set, repeat), rest) = match atoms.split_first() {
+            Some(split) => split,
+            None => return path.is_empty(),
+        };
+        let (min, max) = match repeat {
+            Repeat::Once => (1, 1),
+            Repeat::Optional => (0, 1),
+            Repeat::Any => (0, path.len()),
+            Repeat::AtLeastOnce => (1, path.len()),
+        };
+        let run = path.iter().take_while(|e| set.contains(e)).count();
+        (min..=max.min(run))
+            .rev()
+            .any(|n| Lpe::matches_atoms(rest, &path[n..]))
+    }
+}
+
+//a render pass : the light of the paths matching an expression
+#[derive(Clone)]
+pub struct Pass {
+    pub name: String,
+    pub expression: Lpe,
+}
+
+//the default passes, summing to the beauty image
+const DEFAULT_PASSES: [(&str, &str); 7] = [
+    ("emission", "CL"),
+    ("direct_diffuse", "CDL"),
+    ("direct_specular", "CSL"),
+    ("direct_transmission", "CTL"),
+    ("indirect_diffuse", "CD.+L"),
+    ("indirect_specular", "CS.+L"),
+    ("indirect_transmission", "CT.+L"),
+];
+
+impl Pass {
+    pub fn defaults() -> Vec<Pass> {
+        DEFAULT_PASSES
+            .iter()
+            .map(|(name, expression)| Pass::new(name, expression).unwrap())
+            .collect()
+    }
+
+    pub fn new(name: &str, expression: &str) -> Option<Pass> {
+        Lpe::parse(expression).map(|expression| Pass {
+            name: String::from(name),
+            expression,
+        })
+    }
+
+    //one of the default passes by name, or a custom one as name:expression
+    pub fn from_spec(spec: &str) -> Option<Pass> {
+        match spec.split_once(':') {
+            Some((name, expression)) => Pass::new(name, expression),
+            None => DEFAULT_PASSES
+                .iter()
+                .find(|(name, _)| *name == spec)
+                .and_then(|(name, expression)| Pass::new(name, expression)),
+        }
+    }
+}
+
+pub struct PassBuffers {
+    pub buffers: Vec<(String, FrameBuffer)>,
+}
+
+impl PassBuffers {
+    pub fn new(passes: &[Pass], width: u32, height: u32) -> PassBuffers {
+        PassBuffers {
+            buffers: passes
+                .iter()
+                .map(|pass| (pass.name.clone(), FrameBuffer::new(width, height)))
+                .collect(),
+        }
+    }
+
+    pub fn put_pixel(&mut self, i: u32, j: u32, colors: &[Vec3]) {
+        for ((_, buffer), color) in self.buffers.iter_mut().zip(colors) {
+            buffer.put_pixel(i, j, color.clone());
+        }
+    }
+
+    //exposed like the render but never tone mapped, so that the passes still add up
+    pub fn exr_channels(&self, output: &Output) -> Vec<(String, Vec<f32>)> {
+        let linear = Output {
+            tonemap: None,
+            ..output.clone()
+        };
+        self.buffers
+            .iter()
+            .flat_map(|(name, buffer)| {
+                buffer
+                    .developed(&linear)
+                    .exr_channels(name, &["R", "G", "B"])
+            })
+            .collect()
+    }
+
+    //one file per pass next to the render, like render_emission.png
+    pub fn save_files(&self, output: &Output) -> io::Result<()> {
+        for (name, buffer) in &self.buffers {
+            buffer.save(&sibling_output(output, name))?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,77 @@
+use crate::vector::Vec3;
+
+//lights without a surface : paths can't hit them by chance, so they are only reached by sampling
+//them from the points they light. Intensities are in cd and illuminances in lux
+#[derive(Debug)]
+pub enum PunctualLight {
+    Point {
+        pos: Vec3,
+        intensity: Vec3,
+    },
+    //a point light shining within a cone around dir, fading out between the inner and the
+    //outer cone
+    Spot {
+        pos: Vec3,
+        dir: Vec3,
+        intensity: Vec3,
+        cos_inner: f64,
+        cos_outer: f64,
+    },
+    //light coming from far away, from directions within a cone around dir (toward the light) that
+    //gives soft shadows, or from dir only when it is 0 wide
+    Distant {
+        dir: Vec3,
+        illuminance: Vec3,
+        cos_radius: f64,
+    },
+}
+
+fn smoothstep(edge_0: f64, edge_1: f64, x: f64) -> f64 {
+    if edge_0 >= edge_1 {
+        return if x >= edge_1 { 1.0 } else { 0.0 };
+    }
+    let t = ((x - edge_0) / (edge_1 - edge_0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+impl PunctualLight {
+    //the direction from pos toward the light, the distance to it (infinite for distant lights) and
+    //the light it brings to a surface facing it, divided by the density of the direction
+    pub fn sample(&self, pos: &Vec3, u: (f64, f64)) -> (Vec3, f64, Vec3) {
+        match self {
+            PunctualLight::Point {
+                pos: light_pos,
+                intensity,
+            } => {
+                let to_light = light_pos.minus(pos);
+                let distance = to_light.norm();
+                let irradiance = intensity.scale(1.0 / (distance * distance));
+                (to_light.scale(1.0 / distance), distance, irradiance)
+            }
+            PunctualLight::Spot {
+                pos: light_pos,
+                dir,
+                intensity,
+                cos_inner,
+                cos_outer,
+            } => {
+                let to_light = light_pos.minus(pos);
+                let distance = to_light.norm();
+                let to_light = to_light.scale(1.0 / distance);
+                let cos = -to_light.dot(dir);
+                let falloff = smoothstep(*cos_outer, *cos_inner, cos);
+                let irradiance = intensity.scale(falloff / (distance * distance));
+                (to_light, distance, irradiance)
+            }
+            PunctualLight::Distant {
+                dir,
+                illuminance,
+                cos_radius,
+            } => {
+                //the radiance is the illuminance spread over the cone, which the density cancels
+                let to_light = Vec3::uniform_cone(dir, *cos_radius, u);
+                (to_light, f64::INFINITY, illuminance.clone())
+            }
+        }
+    }
+}
@@ -1,21 +1,43 @@
+use crate::aov::AovPixel;
+use crate::film::Film;
+use crate::integrator::{Integrator, Splat};
+use crate::intrinsics::Intrinsics;
+use crate::lens::{LensSystem, MM_TO_SCENE};
+use crate::motion::Motion;
+use crate::passes::Pass;
+use crate::sampler::Sampler;
 use crate::scene::{Plane, Scene, Sphere};
+use crate::stereo::{Stereo, StereoLayout};
+use crate::tonemap::luminance;
 use crate::vector::{Mat4, Vec3};
 use crate::Material;
 
-use image::Rgb;
-use rand::{thread_rng, Rng};
+use std::f64::consts::PI;
+use std::sync::Arc;
 
 pub struct Context {
     pub scene: Scene,
     pub width: u32,
     pub height: u32,
     pub camera: Camera,
+    pub stereo: Option<Stereo>,
+    pub film: Film,
+    pub passes: Vec<Pass>,
+    pub sampler: Sampler, //cloned for each pixel
+    pub integrator: Box<dyn Integrator>,
+    pub shutter_interval: (f64, f64), //times at which the shutter opens and closes
 }
 
 pub struct Camera {
     pub focal_length: f64,
     pub pos: Vec3,
     pub rot: Mat4,
+    pub shift_x: f64, //offset of the principal point on the image plane, in screen units
+    pub shift_y: f64,
+    pub aspect: f64, //width over height of the image, the window spanning -aspect to aspect in x
+    pub lens: Option<Arc<LensSystem>>, //traced instead of the pinhole when present
+    pub intrinsics: Option<Intrinsics>, //replaces focal_length for the pinhole when present
+    pub motion: Option<Motion>,
 }
 
 impl Camera {
@@ -24,6 +46,134 @@ impl Camera {
             focal_length,
             rot: Mat4::look_at(&pos, &target),
             pos,
+            shift_x: 0.0,
+            shift_y: 0.0,
+            aspect: 1.0,
+            lens: None,
+            intrinsics: None,
+            motion: None,
+        }
+    }
+
+    //extrinsic is the world to camera transform of a calibrated rig, whose camera looks at +z
+    //with y pointing down
+    pub fn from_extrinsic(extrinsic: &Mat4, focal_length: f64) -> Camera {
+        let camera_to_world = extrinsic.rigid_inverse();
+        let flip = Mat4::from_columns(
+            &Vec3 {
+                x: 1.0,
+                ..Vec3::ZERO
+            },
+            &Vec3 {
+                y: -1.0,
+                ..Vec3::ZERO
+            },
+            &Vec3 {
+                z: -1.0,
+                ..Vec3::ZERO
+            },
+        );
+
+        Camera {
+            focal_length,
+            pos: camera_to_world.apply_pos3(&Vec3::ZERO).as_vec3(),
+            rot: camera_to_world.product(&flip),
+            shift_x: 0.0,
+            shift_y: 0.0,
+            aspect: 1.0,
+            lens: None,
+            intrinsics: None,
+            motion: None,
+        }
+    }
+
+    pub fn right(&self) -> Vec3 {
+        self.rot
+            .apply_dir3(&Vec3 {
+                x: 1.0,
+                ..Vec3::ZERO
+            })
+            .as_vec3()
+    }
+
+    pub fn forward(&self) -> Vec3 {
+        self.rot
+            .apply_dir3(&Vec3 {
+                z: -1.0,
+                ..Vec3::ZERO
+            })
+            .as_vec3()
+    }
+
+    //x and y are in the -1 to 1 window, y pointing up
+    pub fn ray_dir(&self, x: f64, y: f64) -> Vec3 {
+        let local_dir = match &self.intrinsics {
+            Some(intrinsics) => intrinsics.ray_dir(x + self.shift_x, y + self.shift_y),
+            None => Vec3 {
+                x: x * self.aspect + self.shift_x,
+                y: y + self.shift_y,
+                z: -self.focal_length,
+            }
+            .normalized(),
+        };
+        self.rot.apply_dir3(&local_dir).as_vec3()
+    }
+
+    //the camera of an eye moved by offset along the right axis, seeing an image of the given
+    //aspect. The image plane is shifted (off-axis projection) so that both eyes agree on objects
+    //at distance convergence
+    pub fn eye(&self, offset: f64, convergence: f64, aspect: f64) -> Camera {
+        Camera {
+            focal_length: self.focal_length,
+            pos: &self.pos + &self.right().scale(offset),
+            rot: Mat4 {
+                elems: self.rot.elems,
+            },
+            shift_x: self.shift_x - offset * self.focal_length / convergence,
+            shift_y: self.shift_y,
+            aspect,
+            lens: self.lens.clone(),
+            intrinsics: self.intrinsics.clone(),
+            motion: self.motion.clone(),
+        }
+    }
+
+    pub fn pos_at(&self, time: f64) -> Vec3 {
+        match &self.motion {
+            Some(motion) => &self.pos + &motion.offset(time),
+            None => self.pos.clone(),
+        }
+    }
+
+    //the primary ray through x, y in the -1 to 1 window. None if the lens blocks it
+    pub fn ray(&self, x: f64, y: f64, sample: &CameraSample) -> Option<Ray> {
+        let pos = self.pos_at(sample.time);
+        let mut ray = match &self.lens {
+            //the sensor width spans the window, its height being narrower for wide images
+            Some(lens) => {
+                let (origin, dir, weight) = lens.sample_ray(
+                    x + self.shift_x,
+                    y / self.aspect + self.shift_y,
+                    sample.lens,
+                    sample.channel,
+                )?;
+                let mut ray = Ray::new(
+                    &pos + &self.rot.apply_dir3(&origin).as_vec3(),
+                    self.rot.apply_dir3(&dir).as_vec3(),
+                );
+                ray.color = weight;
+                ray
+            }
+            None => Ray::new(pos, self.ray_dir(x, y)),
+        };
+        ray.time = sample.time;
+        Some(ray)
+    }
+
+    //focuses the lens (if any) at distance focus_distance in front of the camera
+    pub fn focus(&mut self, focus_distance: f64) {
+        if let Some(lens) = self.lens.as_mut().and_then(Arc::get_mut) {
+            lens.focus(focus_distance / MM_TO_SCENE);
         }
     }
 }
@@ -34,6 +184,20 @@ pub struct Ray {
     pub color: Vec3,
     pub emitted: Vec3,
     pub n: f64,
+    pub time: f64,
+}
+
+impl Ray {
+    pub fn new(origin: Vec3, dir: Vec3) -> Ray {
+        Ray {
+            origin,
+            dir,
+            color: Vec3::ONE,
+            emitted: Vec3::ZERO,
+            n: Material::N_AIR,
+            time: 0.0,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -43,6 +207,7 @@ pub struct Intersection<'a> {
     pub pos: Vec3,
     pub normal: Vec3,
     pub mat: &'a Material,
+    pub object: usize, //index into Scene::objects, set by intersect
 }
 
 impl<'a> Default for Intersection<'a> {
@@ -53,27 +218,40 @@ impl<'a> Default for Intersection<'a> {
             pos: Vec3::ZERO,
             normal: Vec3::ZERO,
             mat: Material::default(),
+            object: 0,
         }
     }
 }
 
-pub trait Primitive {
-    fn intersect(&self, ray: &Ray) -> Intersection;
+pub trait Primitive: std::fmt::Debug {
+    fn intersect(&self, ray: &Ray) -> Intersection<'_>;
+    fn material(&self) -> &Material;
+
+    //for the surfaces lights can be sampled on, None for infinite ones
+    fn area(&self) -> Option<f64> {
+        None
+    }
+
+    //a uniformly distributed point of the surface and its normal
+    fn sample_surface(&self, _u: (f64, f64)) -> Option<(Vec3, Vec3)> {
+        None
+    }
 }
 
-fn intersect<'a>(ray: &Ray, scene: &'a Scene) -> Intersection<'a> {
+pub fn intersect<'a>(ray: &Ray, scene: &'a Scene) -> Intersection<'a> {
     //let mut inter = Intersection::default();
 
     let mut inter = Intersection::default();
     let mut hit = false;
     let mut dist = 0.0;
 
-    for object in &scene.objects {
-        let s_inter = object.intersect(&ray);
+    for (index, object) in scene.objects.iter().enumerate() {
+        let s_inter = object.intersect(ray);
         if s_inter.hit && (s_inter.dist < dist || !hit) {
             hit = true;
             dist = s_inter.dist;
             inter = s_inter;
+            inter.object = index;
         }
     }
 
@@ -81,7 +259,7 @@ fn intersect<'a>(ray: &Ray, scene: &'a Scene) -> Intersection<'a> {
 }
 
 impl Primitive for Sphere {
-    fn intersect(&self, ray: &Ray) -> Intersection {
+    fn intersect(&self, ray: &Ray) -> Intersection<'_> {
         let dp: Vec3 = ray.origin.minus(&self.centre);
         let dir_dot_dp = ray.dir.dot(&dp);
         let discr = dir_dot_dp.powf(2.0) - dp.norm().powf(2.0) + self.radius.powf(2.0);
@@ -103,17 +281,38 @@ impl Primitive for Sphere {
                     dist,
                     pos,
                     normal: normal.scale(if inside { -1.0 } else { 1.0 }),
-                    mat: &self.mat,
+                    mat: self.mat,
+                    object: 0,
                 };
             }
         }
 
         Intersection::default()
     }
+
+    fn material(&self) -> &Material {
+        self.mat
+    }
+
+    fn area(&self) -> Option<f64> {
+        Some(4.0 * PI * self.radius * self.radius)
+    }
+
+    fn sample_surface(&self, u: (f64, f64)) -> Option<(Vec3, Vec3)> {
+        let z = 1.0 - 2.0 * u.0;
+        let r = (1.0 - z * z).max(0.0).sqrt();
+        let phi = 2.0 * PI * u.1;
+        let normal = Vec3 {
+            x: r * phi.cos(),
+            y: r * phi.sin(),
+            z,
+        };
+        Some((&self.centre + &normal.scale(self.radius), normal))
+    }
 }
 
 impl Primitive for Plane {
-    fn intersect(&self, ray: &Ray) -> Intersection {
+    fn intersect(&self, ray: &Ray) -> Intersection<'_> {
         let mu = -ray.origin.minus(&self.pos).dot(&self.normal) / ray.dir.dot(&self.normal);
 
         Intersection {
@@ -122,115 +321,157 @@ impl Primitive for Plane {
             pos: &ray.origin + &ray.dir.scale(mu),
             normal: self.normal.clone(),
             mat: self.mat,
+            object: 0,
         }
     }
+
+    fn material(&self) -> &Material {
+        self.mat
+    }
 }
 
-fn reflect(dir: &Vec3, normal: &Vec3, roughness: f64) -> Vec3 {
-    let reflected_dir = dir
-        .minus(&normal.scale(2.0 * normal.dot(&dir)))
-        .normalized();
+pub fn reflect(dir: &Vec3, normal: &Vec3, roughness: f64, u: (f64, f64)) -> Vec3 {
+    let reflected_dir = dir.minus(&normal.scale(2.0 * normal.dot(dir))).normalized();
 
-    //let random_dir = &Vec3::random_vector_in_hemisphere(normal);
-    let random_dir = &Vec3::cosine_weighted_hemisphere(normal);
+    //let random_dir = &Vec3::random_vector_in_hemisphere(normal, sampler);
+    let random_dir = &Vec3::cosine_weighted_hemisphere(normal, u);
 
     (&(&reflected_dir * (1.0 - roughness)) + &random_dir.scale(roughness)).normalized()
 }
 
-fn refract(dir: &Vec3, normal: &Vec3, n1: f64, n2: f64) -> Vec3 {
+pub fn refract(dir: &Vec3, normal: &Vec3, n1: f64, n2: f64) -> Vec3 {
     let normal = normal.scale(-1.0);
-    let nd = normal.dot(&dir);
+    let nd = normal.dot(dir);
     let sqr_root = (nd.powf(2.0) + (n2 / n1).powf(2.0) - 1.0).sqrt();
 
     (&(&(&normal * (sqr_root - nd)) + dir) * (n1 / n2)).normalized()
 }
 
-pub fn pixel_shader(ctx: &Context, i: u32, j: u32, bounces: u8, samples_per_pixel: u32) -> Rgb<u8> {
-    //for now, we use orthographic projection in the -1 to 1 window, looking at z
-    let x = 2.0 * (i as f64) / (ctx.width as f64) - 1.0;
-    let y = 2.0 * ((ctx.height - j - 1) as f64) / (ctx.height as f64) - 1.0;
+//maps the center of pixel (i, j) of a width x height image to the -1 to 1 window, y pointing up
+pub fn screen_coords(i: u32, j: u32, width: u32, height: u32) -> (f64, f64) {
+    let x = 2.0 * (i as f64 + 0.5) / (width as f64) - 1.0;
+    let y = 1.0 - 2.0 * (j as f64 + 0.5) / (height as f64);
+    (x, y)
+}
+
+//the random numbers a camera needs for a primary ray
+pub struct CameraSample {
+    pub time: f64,
+    pub lens: (f64, f64),
+    pub channel: f64, //wavelength, for dispersive lenses
+}
+
+pub fn camera_ray(ctx: &Context, i: u32, j: u32, sampler: &mut Sampler) -> Option<Ray> {
+    let (open, close) = ctx.shutter_interval;
+    let sample = CameraSample {
+        time: open + (close - open) * sampler.get_1d(),
+        lens: sampler.get_2d(),
+        channel: sampler.get_1d(),
+    };
 
     //let ray = Ray{origin : Vec3{x : x, y : y, z : 0.0}, dir : Vec3{x : 0.0, y : 0.0, z : -1.0}}; //ORTHOGRAPHIC PROJETION
+    match &ctx.stereo {
+        Some(stereo) => stereo.camera_ray(&ctx.camera, i, j, ctx.width, ctx.height, &sample),
+        None => {
+            let (x, y) = screen_coords(i, j, ctx.width, ctx.height);
+            ctx.camera.ray(x, y, &sample)
+        }
+    }
+}
+
+//cosine between a primary ray and the optical axis, for vignetting
+pub fn off_axis_cos(ctx: &Context, ray: &Ray) -> f64 {
+    match &ctx.stereo {
+        Some(Stereo {
+            layout: StereoLayout::Omnidirectional,
+            ..
+        }) => 1.0, //panoramas have no optical axis
+        _ => ray.dir.dot(&ctx.camera.forward()),
+    }
+}
 
+//the cosine the film darkens the ray with, lenses already weighting their rays by the falloff
+pub fn falloff_cos(ctx: &Context, ray: &Ray) -> f64 {
+    match &ctx.camera.lens {
+        Some(_) => 1.0,
+        None => off_axis_cos(ctx, ray),
+    }
+}
+
+//what pixel_shader computes for a pixel, all developed by the film but the aovs
+pub struct ShadedPixel {
+    pub color: Vec3,
+    pub aov: AovPixel,
+    pub passes: Vec<Vec3>,  //one color per pass of the context
+    pub luminance_sq: f64,  //mean of the squared luminance of the samples, for their variance
+    pub splats: Vec<Splat>, //summed over the samples
+}
+
+pub fn pixel_shader(
+    ctx: &Context,
+    i: u32,
+    j: u32,
+    first_sample: u32,
+    samples_per_pixel: u32,
+) -> ShadedPixel {
     let mut acc_color = Vec3::ZERO;
-    let cam_dir = ctx
-        .camera
-        .rot
-        .apply_dir3(
-            &Vec3 {
-                x,
-                y,
-                z: -ctx.camera.focal_length,
-            }
-            .normalized(),
-        )
-        .as_vec3();
-    for _ in 0..samples_per_pixel {
-        let mut ray = Ray {
-            origin: ctx.camera.pos.clone(),
-            dir: cam_dir.clone(),
-            color: Vec3::ONE,
-            emitted: Vec3::ZERO,
-            n: Material::N_AIR,
+    let mut acc_luminance_sq = 0.0;
+    let mut aov = AovPixel::default();
+    let mut ids_pending = true;
+    let mut acc_passes = vec![Vec3::ZERO; ctx.passes.len()];
+    let mut sample_passes = vec![Vec3::ZERO; ctx.passes.len()];
+    let mut splats = vec![];
+    let mut sampler = ctx.sampler.clone();
+    for index in first_sample..first_sample + samples_per_pixel {
+        sampler.start_sample(i, j, index);
+        let ray = match camera_ray(ctx, i, j, &mut sampler) {
+            Some(ray) => ray,
+            None => continue, //blocked by the lens
         };
-
-        let mut iter = 0;
-        loop {
-            let int = intersect(&ray, &ctx.scene);
+        let cos_theta = off_axis_cos(ctx, &ray);
+        let falloff_cos = falloff_cos(ctx, &ray);
+
+        let int = intersect(&ray, &ctx.scene);
+        if int.hit {
+            aov.hits += 1.0;
+            aov.depth += int.dist * cos_theta;
+            aov.normal = &aov.normal + &int.normal;
+            aov.albedo = &aov.albedo + &int.mat.reflectance();
+            aov.position = &aov.position + &int.pos;
+        }
+        if ids_pending {
+            ids_pending = false;
             if int.hit {
-                let dotp = -int.normal.dot(&ray.dir);
-                let k_fresnel =
-                    int.mat.fresnel_0 + (1.0 - int.mat.fresnel_0) * (1.0 - dotp).powf(5.0);
-
-                ray.emitted = &ray.emitted + &(&int.mat.emissive.mult(&ray.color));
-
-                //dir stuff TODO refactor this mess
-                let is_specular_bounce = thread_rng().gen::<f64>() < int.mat.specularity;
-                if is_specular_bounce {
-                    ray.dir = reflect(&ray.dir, &int.normal, 0.0);
-                    ray.color = ray.color.mult(&int.mat.specular).scale(k_fresnel);
-                } else {
-                    let is_refraction = thread_rng().gen::<f64>() < (1.0 - k_fresnel);
-                    if is_refraction {
-                        let next_n;
-                        if ray.n == int.mat.n {
-                            next_n = Material::N_AIR;
-                        } else {
-                            next_n = int.mat.n;
-                        }
-                        ray.dir = refract(&ray.dir, &int.normal, ray.n, next_n);
-                        ray.n = next_n;
-                        let scaler = int.mat.transparency;
-                        let new_color = ray.color.mult(&int.mat.albedo).scale(scaler);
-                        ray.color = new_color;
-                        ray.origin = &int.pos + &int.normal.scale(-0.001);
-                    } else {
-                        ray.dir = reflect(&ray.dir, &int.normal, int.mat.roughness);
-                        //let cos_theta = ray.dir.dot(&int.normal);
-                        ray.color = ray.color.mult(&int.mat.albedo);
-                        ray.origin = &int.pos + &int.normal.scale(0.001);
-                    }
-                }
-            } else {
-                break;
-            }
-
-            iter += 1;
-            if iter > bounces {
-                break;
+                aov.object_id = int.object as f64;
+                aov.material_id = ctx.scene.material_id(int.mat).map_or(-1.0, |id| id as f64);
             }
         }
 
-        acc_color = &acc_color + &ray.emitted;
+        //every sample is developed with the falloff of its own ray
+        sample_passes.fill(Vec3::ZERO);
+        let radiance =
+            ctx.integrator
+                .radiance(ctx, ray, int, &mut sampler, &mut sample_passes, &mut splats);
+        let color = ctx.film.develop(&radiance, falloff_cos);
+        acc_luminance_sq += luminance(&color).powi(2);
+        acc_color = &acc_color + &color;
+        for (acc, pass) in acc_passes.iter_mut().zip(&sample_passes) {
+            *acc = &*acc + &ctx.film.develop(pass, falloff_cos);
+        }
     }
 
-    acc_color = acc_color.scale(1.0 / (samples_per_pixel as f64));
-
-    let f_to_u8 = |f: f64| (255.0 * f.max(0.0).min(1.0)) as u8;
-
-    Rgb([
-        f_to_u8(acc_color.x),
-        f_to_u8(acc_color.y),
-        f_to_u8(acc_color.z),
-    ])
+    let inv_spp = 1.0 / (samples_per_pixel as f64);
+    acc_color = acc_color.scale(inv_spp);
+    let inv_hits = 1.0 / aov.hits.max(1.0);
+    aov.depth *= inv_hits;
+    aov.normal = aov.normal.scale(inv_hits);
+    aov.albedo = aov.albedo.scale(inv_hits);
+    aov.position = aov.position.scale(inv_hits);
+    ShadedPixel {
+        color: acc_color,
+        aov,
+        passes: acc_passes.iter().map(|acc| acc.scale(inv_spp)).collect(),
+        luminance_sq: acc_luminance_sq * inv_spp,
+        splats,
+    }
 }
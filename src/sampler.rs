@@ -0,0 +1,315 @@
+use std::sync::Arc;
+
+use crate::bluenoise::BlueNoiseMask;
+
+//the sequences the samples of a pixel are drawn from
+#[derive(Clone, Copy)]
+pub enum SamplerKind {
+    Independent, //uniform random numbers
+    Stratified,  //jittered strata, shuffled in each dimension
+    Halton,      //radical inverses in prime bases, with digits scrambled for each pixel
+    Sobol,       //Owen scrambled Sobol (0,2) sequence, shuffled in each dimension
+    BlueNoise,   //the same Sobol points in every pixel, shifted by a blue noise mask
+}
+
+impl SamplerKind {
+    pub fn from_name(name: &str) -> Option<SamplerKind> {
+        match name {
+            "independent" | "random" => Some(SamplerKind::Independent),
+            "stratified" | "jittered" => Some(SamplerKind::Stratified),
+            "halton" => Some(SamplerKind::Halton),
+            "sobol" => Some(SamplerKind::Sobol),
+            "blue_noise" | "bluenoise" => Some(SamplerKind::BlueNoise),
+            _ => None,
+        }
+    }
+}
+
+//dimensions used by a primary ray : time, lens (2d) and wavelength
+pub const CAMERA_DIMENSIONS: u32 = 4;
+//dimensions used by a bounce : lobe choice, refraction choice, direction (2d), russian roulette,
+//environment (2d), punctual light choice and its direction (2d)
+pub const BOUNCE_DIMENSIONS: u32 = 10;
+
+const BLUE_NOISE_SIZE: usize = 64;
+
+const PRIMES: [u32; 64] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131, 137, 139, 149, 151, 157, 163, 167, 173, 179, 181, 191, 193,
+    197, 199, 211, 223, 227, 229, 233, 239, 241, 251, 257, 263, 269, 271, 277, 281, 283, 293, 307,
+    311,
+];
+
+//deterministic samples : every sample of every pixel is a point of the sequence of that pixel,
+//derived from the seed, the pixel and the sample index, so that a render doesn't depend on the
+//number of threads, the tile order or on being resumed from a checkpoint. The splats of bdpt and
+//mlt are the exception : they add up in the order tiles are handed out, so their rounding only
+//stays the same across thread counts. Each bounce starts at a fixed dimension, so that the same
+//dimensions always sample the same thing
+#[derive(Clone)]
+pub struct Sampler {
+    kind: SamplerKind,
+    seed: u64,
+    strata: u32, //size of the strata sets of the stratified sampler
+    mask: Option<Arc<BlueNoiseMask>>,
+    x: u32,
+    y: u32,
+    pixel: u64,
+    index: u32,
+    dimension: u32,
+    replay: Option<Vec<f64>>, //numbers given instead of the sequence, random past their end
+}
+
+//splitmix64 finalizer, a good enough hash of 64 bits
+pub(crate) fn mix(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+fn to_unit(x: u32) -> f64 {
+    x as f64 / 4294967296.0
+}
+
+//a random permutation of 0..len picked by seed, from Kensler's Correlated Multi-Jittered Sampling
+fn permute(mut i: u32, len: u32, seed: u32) -> u32 {
+    let mut w = len - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= seed;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= seed >> 16;
+        i ^= (i & w) >> 4;
+        i ^= seed >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= seed >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | seed >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < len {
+            return i.wrapping_add(seed) % len;
+        }
+    }
+}
+
+//the digits of index in base, mirrored around the radix point, each digit going through a random
+//permutation (picked by seed and the position of the digit). Without it the large bases of the
+//last dimensions would put all of the first samples close to 0
+fn scrambled_radical_inverse(base: u32, mut index: u32, seed: u64) -> f64 {
+    let inv_base = 1.0 / base as f64;
+    let mut inv = inv_base;
+    let mut result = 0.0;
+    let mut position = 0;
+    //past the digits of index, the zeros are permuted too, up to the precision of a f64
+    while inv > 1e-16 {
+        let digit_seed = mix(seed ^ position) as u32;
+        result += permute(index % base, base, digit_seed) as f64 * inv;
+        index /= base;
+        inv *= inv_base;
+        position += 1;
+    }
+    result.min(1.0 - f64::EPSILON)
+}
+
+//the first two dimensions of the Sobol sequence, as 32 bit fractions
+fn sobol_2d(index: u32) -> (u32, u32) {
+    let mut x = 0;
+    let mut y = 0;
+    let mut v = 1 << 31;
+    for bit in 0..32 {
+        if (index >> bit) & 1 == 1 {
+            x ^= 1 << (31 - bit);
+            y ^= v;
+        }
+        v ^= v >> 1;
+    }
+    (x, y)
+}
+
+//Laine and Karras' hash, a random permutation of the bits that only depends on lower bits
+fn laine_karras(mut x: u32, seed: u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    x
+}
+
+//Owen scrambling, as in Burley's Practical Hash-based Owen Scrambling
+fn owen_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras(x.reverse_bits(), seed).reverse_bits()
+}
+
+impl Sampler {
+    pub fn new(kind: SamplerKind, seed: u64, strata: u32) -> Sampler {
+        Sampler {
+            kind,
+            seed,
+            strata: strata.max(1),
+            mask: match kind {
+                SamplerKind::BlueNoise => Some(Arc::new(BlueNoiseMask::new(BLUE_NOISE_SIZE, seed))),
+                _ => None,
+            },
+            x: 0,
+            y: 0,
+            pixel: 0,
+            index: 0,
+            dimension: 0,
+            replay: None,
+        }
+    }
+
+    pub fn start_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.x = x;
+        self.y = y;
+        self.pixel = (y as u64) << 32 | x as u64;
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    //the numbers of a sample that belongs to no pixel, like the photons of a photon map : the
+    //samples of a set are a sequence of their own
+    pub fn start_global_sample(&mut self, set: u32, index: u32) {
+        self.x = index;
+        self.y = set;
+        self.pixel = 1 << 63 | set as u64;
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    //a sampler giving back the given numbers, dimension after dimension, for integrators that
+    //choose the numbers of the samples themselves (metropolis)
+    pub fn replaying(&self, numbers: &[f64]) -> Sampler {
+        Sampler {
+            replay: Some(numbers.to_vec()),
+            dimension: 0,
+            ..self.clone()
+        }
+    }
+
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    //skips to the dimensions of the given bounce, 0 being the first hit
+    pub fn start_bounce(&mut self, bounce: u32) {
+        self.dimension = CAMERA_DIMENSIONS + bounce * BOUNCE_DIMENSIONS;
+    }
+
+    //a hash of the pixel and the dimension (and whatever else is given)
+    fn hash(&self, dimension: u32, salt: u64) -> u64 {
+        mix(mix(mix(self.seed ^ mix(self.pixel)) ^ dimension as u64) ^ salt)
+    }
+
+    //the same for every pixel
+    fn global_hash(&self, dimension: u32) -> u64 {
+        mix(mix(self.seed) ^ dimension as u64)
+    }
+
+    //a point of the Sobol sequence, the index being shuffled and the point Owen scrambled
+    fn sobol(&self, seed: u32) -> (f64, f64) {
+        let (x, y) = sobol_2d(owen_scramble(self.index, seed));
+        (
+            to_unit(owen_scramble(x, seed ^ 0x9e3779b9)),
+            to_unit(owen_scramble(y, seed ^ 0x7f4a7c15)),
+        )
+    }
+
+    //blue noise over the pixels, the mask being shifted by a random offset for each dimension
+    fn blue_noise(&self, dimension: u32) -> f64 {
+        let offset = self.global_hash(dimension);
+        self.mask.as_ref().unwrap().get(
+            self.x as u64 + (offset & 0xffff),
+            self.y as u64 + (offset >> 48),
+        )
+    }
+
+    fn random(&self, dimension: u32) -> f64 {
+        (self.hash(dimension, self.index as u64) >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    //uniform in [0, 1)
+    pub fn get_1d(&mut self) -> f64 {
+        let dimension = self.dimension;
+        self.dimension += 1;
+        if let Some(numbers) = &self.replay {
+            return match numbers.get(dimension as usize) {
+                Some(&number) => number,
+                None => self.random(dimension),
+            };
+        }
+        match self.kind {
+            SamplerKind::Independent => self.random(dimension),
+            SamplerKind::Stratified => {
+                //the strata of the dimension are visited in a random order, each set of
+                //n samples being shuffled differently
+                let n = self.strata;
+                let set = (self.index / n) as u64;
+                let stratum = permute(self.index % n, n, self.hash(dimension, set) as u32);
+                (stratum as f64 + self.random(dimension)) / n as f64
+            }
+            SamplerKind::Halton => match PRIMES.get(dimension as usize) {
+                Some(base) => {
+                    scrambled_radical_inverse(*base, self.index, self.hash(dimension, u64::MAX))
+                }
+                None => self.random(dimension),
+            },
+            SamplerKind::Sobol => self.sobol(self.hash(dimension, u64::MAX) as u32).0,
+            SamplerKind::BlueNoise => {
+                let sample = self.sobol(self.global_hash(dimension) as u32).0;
+                (sample + self.blue_noise(dimension)).fract()
+            }
+        }
+    }
+
+    //a point of the unit square, both coordinates being well distributed together
+    pub fn get_2d(&mut self) -> (f64, f64) {
+        let dimension = self.dimension;
+        if self.replay.is_some() {
+            return (self.get_1d(), self.get_1d());
+        }
+        match self.kind {
+            SamplerKind::Stratified => {
+                //a jittered grid of n x n strata, at most as many as a u32 counts
+                self.dimension += 2;
+                let n = (self.strata as f64).sqrt().ceil().min(u16::MAX as f64) as u32;
+                let set = (self.index / (n * n)) as u64;
+                let cell = permute(
+                    self.index % (n * n),
+                    n * n,
+                    self.hash(dimension, set) as u32,
+                );
+                (
+                    ((cell % n) as f64 + self.random(dimension)) / n as f64,
+                    ((cell / n) as f64 + self.random(dimension + 1)) / n as f64,
+                )
+            }
+            SamplerKind::Sobol => {
+                self.dimension += 2;
+                self.sobol(self.hash(dimension, u64::MAX) as u32)
+            }
+            SamplerKind::BlueNoise => {
+                self.dimension += 2;
+                let (x, y) = self.sobol(self.global_hash(dimension) as u32);
+                (
+                    (x + self.blue_noise(dimension)).fract(),
+                    (y + self.blue_noise(dimension + 1)).fract(),
+                )
+            }
+            _ => (self.get_1d(), self.get_1d()),
+        }
+    }
+}
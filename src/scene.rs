@@ -1,6 +1,10 @@
+use crate::environment::Environment;
+use crate::motion::{Keyframe, Motion, Moving};
+use crate::punctual::PunctualLight;
 use crate::render::{Intersection, Primitive, Ray};
-use crate::{Mat4, Material, Vec3};
+use crate::{Material, Vec3};
 
+#[derive(Debug)]
 pub struct Parallelogram {
     pub pos: Vec3, //A corner of the rectangle
     v1: Vec3,      //one side of the rectangle, adjacent to the position
@@ -26,7 +30,7 @@ impl Parallelogram {
 }
 
 impl Primitive for Parallelogram {
-    fn intersect(&self, ray: &Ray) -> Intersection {
+    fn intersect(&self, ray: &Ray) -> Intersection<'_> {
         let mu = -ray.origin.minus(&self.pos).dot(&self.normal) / ray.dir.dot(&self.normal);
         let pos = &ray.origin + &ray.dir.scale(mu);
         let hit_plane_pos = pos.minus(&self.pos);
@@ -43,6 +47,7 @@ impl Primitive for Parallelogram {
                 pos,
                 normal: self.normal.scale(if inside { -1.0 } else { 1.0 }), //the normal must be opposing incoming ray
                 mat: self.mat,
+                object: 0,
             };
             //println!("inter at plgm : {:?}", inter);
             //println!("normal at plgm : {}, ray.dir : {}", inter.normal, ray.dir);
@@ -51,14 +56,29 @@ impl Primitive for Parallelogram {
             Intersection::default()
         }
     }
+
+    fn material(&self) -> &Material {
+        self.mat
+    }
+
+    fn area(&self) -> Option<f64> {
+        Some(self.l1 * self.l2 * self.v1.cross(&self.v2).norm())
+    }
+
+    fn sample_surface(&self, u: (f64, f64)) -> Option<(Vec3, Vec3)> {
+        let pos = &(&self.pos + &self.v1.scale(u.0 * self.l1)) + &self.v2.scale(u.1 * self.l2);
+        Some((pos, self.normal.clone()))
+    }
 }
 
+#[derive(Debug)]
 pub struct Sphere {
     pub centre: Vec3,
     pub radius: f64,
     pub mat: &'static Material,
 }
 
+#[derive(Debug)]
 pub struct Plane {
     pub normal: Vec3,
     pub pos: Vec3,
@@ -66,24 +86,24 @@ pub struct Plane {
 }
 
 fn cube(pos: Vec3, size: f64, mat: &'static Material) -> [Parallelogram; 6] {
-    let X = Vec3 {
+    let x_side = Vec3 {
         x: size,
         y: 0.0,
         z: 0.0,
     };
-    let Y = Vec3 {
+    let y_side = Vec3 {
         x: 0.0,
         y: size,
         z: 0.0,
     };
-    let Z = Vec3 {
+    let z_side = Vec3 {
         x: 0.0,
         y: 0.0,
         z: size,
     };
-    let mX = X.scale(-1.0);
-    let mY = Y.scale(-1.0);
-    let mZ = Z.scale(-1.0);
+    let neg_x = x_side.scale(-1.0);
+    let neg_y = y_side.scale(-1.0);
+    let neg_z = z_side.scale(-1.0);
 
     let opp_pos = &pos
         + &Vec3 {
@@ -93,21 +113,64 @@ fn cube(pos: Vec3, size: f64, mat: &'static Material) -> [Parallelogram; 6] {
         };
 
     [
-        Parallelogram::new(pos.clone(), Z.clone(), X.clone(), mat), //bottom
-        Parallelogram::new(pos.clone(), X.clone(), Y.clone(), mat), //near
-        Parallelogram::new(pos.clone(), Y.clone(), Z.clone(), mat), //left
-        Parallelogram::new(opp_pos.clone(), mX.clone(), mZ.clone(), mat), //top
-        Parallelogram::new(opp_pos.clone(), mY.clone(), mX.clone(), mat), //far
-        Parallelogram::new(opp_pos.clone(), mZ.clone(), mY.clone(), mat), //right
+        Parallelogram::new(pos.clone(), z_side.clone(), x_side.clone(), mat), //bottom
+        Parallelogram::new(pos.clone(), x_side.clone(), y_side.clone(), mat), //near
+        Parallelogram::new(pos.clone(), y_side.clone(), z_side.clone(), mat), //left
+        Parallelogram::new(opp_pos.clone(), neg_x.clone(), neg_z.clone(), mat), //top
+        Parallelogram::new(opp_pos.clone(), neg_y.clone(), neg_x.clone(), mat), //far
+        Parallelogram::new(opp_pos.clone(), neg_z.clone(), neg_y.clone(), mat), //right
     ]
 }
 
 pub struct Scene {
     pub objects: Vec<Box<dyn Primitive + Send + Sync>>,
+    pub environment: Option<Environment>, //light of the rays leaving the scene
+    pub punctual_lights: Vec<PunctualLight>,
+}
+
+//64 bit FNV-1a hash, to tell scenes apart
+pub fn fnv1a(bytes: impl IntoIterator<Item = u8>) -> u64 {
+    bytes.into_iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
 }
 
 impl Scene {
-    pub fn _scene_1() -> Scene {
+    //FNV-1a hash of the description of every object and light, to tell whether a scene changed
+    pub fn hash(&self) -> u64 {
+        let mut description = format!("{:?}", self.objects);
+        if let Some(environment) = &self.environment {
+            description += &format!("{:?}", environment);
+        }
+        if !self.punctual_lights.is_empty() {
+            description += &format!("{:?}", self.punctual_lights);
+        }
+        fnv1a(description.bytes())
+    }
+
+    //rank of the material among the distinct ones of the scene (by address), in object order
+    pub fn material_id(&self, mat: &Material) -> Option<usize> {
+        let mut materials: Vec<&Material> = vec![];
+        for object in &self.objects {
+            let object_mat = object.material();
+            if !materials.iter().any(|m| std::ptr::eq(*m, object_mat)) {
+                materials.push(object_mat);
+            }
+        }
+        materials.iter().position(|m| std::ptr::eq(*m, mat))
+    }
+
+    pub fn from_name(name: &str) -> Option<Scene> {
+        match name {
+            "scene_1" => Some(Scene::scene_1()),
+            "scene_2" => Some(Scene::scene_2()),
+            "cornell" => Some(Scene::cornell_box()),
+            "cornell_motion" => Some(Scene::cornell_box_in_motion()),
+            _ => None,
+        }
+    }
+
+    pub fn scene_1() -> Scene {
         const MAT_1: Material = Material {
             albedo: Vec3 {
                 x: 1.0,
@@ -309,10 +372,14 @@ impl Scene {
             objects.push(Box::new(s));
         }
 
-        Scene { objects }
+        Scene {
+            objects,
+            environment: None,
+            punctual_lights: vec![],
+        }
     }
 
-    pub fn _scene_2() -> Scene {
+    pub fn scene_2() -> Scene {
         const SPHERE_MAT_1: Material = Material {
             albedo: Vec3 {
                 x: 1.0,
@@ -485,7 +552,7 @@ impl Scene {
             },
             mat: &PLANE_MAT_3,
         };
-        let plane4 = Plane {
+        let _plane4 = Plane {
             normal: Vec3 {
                 x: 0.0,
                 y: 0.0,
@@ -498,7 +565,7 @@ impl Scene {
             },
             mat: &PLANE_MAT_2,
         };
-        let plane5 = Plane {
+        let _plane5 = Plane {
             normal: Vec3 {
                 x: -1.0,
                 y: 0.0,
@@ -537,6 +604,8 @@ impl Scene {
                 Box::new(plane3),
                 Box::new(emissive_plane),
             ],
+            environment: None,
+            punctual_lights: vec![],
         }
     }
 
@@ -664,7 +733,7 @@ impl Scene {
             &Material::WHITE_LIGHT,
         );
 
-        let sphere1 = Sphere {
+        let _sphere1 = Sphere {
             centre: Vec3 {
                 x: -0.4,
                 y: -0.3,
@@ -673,7 +742,7 @@ impl Scene {
             radius: 0.25,
             mat: &Material::DIFFUSE,
         };
-        let sphere2 = Sphere {
+        let _sphere2 = Sphere {
             centre: Vec3 {
                 x: -0.4,
                 y: 0.3,
@@ -682,7 +751,7 @@ impl Scene {
             radius: 0.25,
             mat: &Material::GLOSSY,
         };
-        let sphere3 = Sphere {
+        let _sphere3 = Sphere {
             centre: Vec3 {
                 x: 0.4,
                 y: -0.3,
@@ -700,7 +769,7 @@ impl Scene {
             radius: 0.3,
             mat: &Material::FRESNEL_GLASS,
         };
-        let sphere5 = Sphere {
+        let _sphere5 = Sphere {
             centre: Vec3 {
                 x: 0.0,
                 y: -0.65,
@@ -720,7 +789,7 @@ impl Scene {
             &Material::FRESNEL_GLASS,
         );
 
-        let square1 = Parallelogram::new(
+        let _square1 = Parallelogram::new(
             Vec3::ZERO,
             Vec3 {
                 x: 0.0,
@@ -756,6 +825,55 @@ impl Scene {
 
         //objects.push(Box::new(square1));
 
-        Scene { objects }
+        Scene {
+            objects,
+            environment: None,
+            punctual_lights: vec![],
+        }
+    }
+
+    //the cornell box with a sphere crossing it during the shutter interval 0 to 1, and another one
+    //bouncing on the ground
+    pub fn cornell_box_in_motion() -> Scene {
+        let mut scene = Scene::cornell_box();
+
+        scene.objects.push(Box::new(Moving {
+            object: Box::new(Sphere {
+                centre: Vec3 {
+                    x: 0.2,
+                    y: -0.2,
+                    z: -0.3,
+                },
+                radius: 0.15,
+                mat: &Material::DIFFUSE,
+            }),
+            motion: Motion::Linear(Vec3 {
+                x: 0.4,
+                y: 0.0,
+                z: 0.0,
+            }),
+        }));
+
+        let bounce = |time: f64, height: f64| Keyframe {
+            time,
+            offset: Vec3 {
+                y: height,
+                ..Vec3::ZERO
+            },
+        };
+        scene.objects.push(Box::new(Moving {
+            object: Box::new(Sphere {
+                centre: Vec3 {
+                    x: 0.7,
+                    y: -0.85,
+                    z: -0.1,
+                },
+                radius: 0.15,
+                mat: &Material::GLOSSY,
+            }),
+            motion: Motion::Keyframed(vec![bounce(0.0, 0.6), bounce(0.5, 0.0), bounce(1.0, 0.4)]),
+        }));
+
+        scene
     }
 }
@@ -0,0 +1,175 @@
+use crossbeam::channel;
+use crossbeam::deque::{Injector, Steal};
+use std::thread;
+
+use crate::accumulator::Accumulator;
+use crate::render::{pixel_shader, Context, ShadedPixel};
+
+//the order tiles are handed out in, which is also the order the image fills in
+#[derive(Clone, Copy)]
+pub enum TileOrder {
+    Scanline,
+    Spiral,  //from the center outwards, where the subject usually is
+    Hilbert, //neighbouring tiles one after another, for cache coherence
+}
+
+impl TileOrder {
+    pub fn from_name(name: &str) -> Option<TileOrder> {
+        match name {
+            "scanline" => Some(TileOrder::Scanline),
+            "spiral" => Some(TileOrder::Spiral),
+            "hilbert" => Some(TileOrder::Hilbert),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Tile {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+//what a thread computed for a tile, merged into the accumulator by the calling thread
+struct TileResult {
+    index: usize, //in the order tiles are handed out
+    tile: Tile,
+    pixels: Vec<Option<(ShadedPixel, u32)>>, //row by row, None for pixels without samples
+}
+
+pub struct Scheduler {
+    pub threads: u32,
+    pub tile_size: u32,
+    pub order: TileOrder,
+}
+
+//index of (x, y) along the Hilbert curve filling a n x n grid, n being a power of two
+fn hilbert_index(n: u32, mut x: u32, mut y: u32) -> u64 {
+    let mut d = 0;
+    let mut s = n / 2;
+    while s > 0 {
+        let rx = (x & s > 0) as u32;
+        let ry = (y & s > 0) as u32;
+        d += (s as u64) * (s as u64) * ((3 * rx) ^ ry) as u64;
+        //rotate the quadrant
+        if ry == 0 {
+            if rx == 1 {
+                x = n - 1 - x;
+                y = n - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        s /= 2;
+    }
+    d
+}
+
+impl Scheduler {
+    pub fn tiles(&self, width: u32, height: u32) -> Vec<Tile> {
+        let size = self.tile_size.max(1);
+        let (tiles_x, tiles_y) = (width.div_ceil(size), height.div_ceil(size));
+        let mut coords: Vec<(u32, u32)> = (0..tiles_y)
+            .flat_map(|ty| (0..tiles_x).map(move |tx| (tx, ty)))
+            .collect();
+
+        match self.order {
+            TileOrder::Scanline => (),
+            TileOrder::Spiral => {
+                //ring by ring around the center, each ring going around clockwise
+                let (cx, cy) = ((tiles_x as f64 - 1.0) / 2.0, (tiles_y as f64 - 1.0) / 2.0);
+                let key = |&(tx, ty): &(u32, u32)| {
+                    let (dx, dy) = (tx as f64 - cx, ty as f64 - cy);
+                    let ring = dx.abs().max(dy.abs()).round() as i64;
+                    let angle = (dy.atan2(dx) * 1e6) as i64;
+                    (ring, angle)
+                };
+                coords.sort_by_key(key);
+            }
+            TileOrder::Hilbert => {
+                let n = tiles_x.max(tiles_y).next_power_of_two();
+                coords.sort_by_key(|&(tx, ty)| hilbert_index(n, tx, ty));
+            }
+        }
+
+        coords
+            .into_iter()
+            .map(|(tx, ty)| Tile {
+                x: tx * size,
+                y: ty * size,
+                width: size.min(width - tx * size),
+                height: size.min(height - ty * size),
+            })
+            .collect()
+    }
+
+    //adds spp[p] samples to every pixel p. Threads pull tiles from a shared queue and send back
+    //their buffers, so that nothing is locked per pixel
+    pub fn render_pass(&self, ctx: &Context, acc: &mut Accumulator, spp: &[u32]) {
+        let width = ctx.width;
+        let first_sample = acc.samples.clone();
+        let queue = Injector::new();
+        let tiles = self.tiles(ctx.width, ctx.height);
+        let n_tiles = tiles.len();
+        for tile in tiles.into_iter().enumerate() {
+            queue.push(tile);
+        }
+        let (sender, receiver) = channel::unbounded();
+
+        thread::scope(|s| {
+            for _ in 0..self.threads.max(1) {
+                let sender = sender.clone();
+                let queue = &queue;
+                let first_sample = &first_sample;
+                s.spawn(move || loop {
+                    let (index, tile): (usize, Tile) = match queue.steal() {
+                        Steal::Success(tile) => tile,
+                        Steal::Retry => continue,
+                        Steal::Empty => break,
+                    };
+                    let pixels = (tile.y..tile.y + tile.height)
+                        .flat_map(|j| (tile.x..tile.x + tile.width).map(move |i| (i, j)))
+                        .map(|(i, j)| {
+                            let p = (j * width + i) as usize;
+                            if spp[p] == 0 {
+                                None
+                            } else {
+                                let shaded = pixel_shader(ctx, i, j, first_sample[p], spp[p]);
+                                Some((shaded, spp[p]))
+                            }
+                        })
+                        .collect();
+                    sender
+                        .send(TileResult {
+                            index,
+                            tile,
+                            pixels,
+                        })
+                        .unwrap();
+                });
+            }
+            drop(sender);
+
+            //merged in the order tiles were handed out whatever thread finished first, as
+            //splats from different tiles add up in the same pixels
+            let mut pending: Vec<Option<TileResult>> = (0..n_tiles).map(|_| None).collect();
+            let mut next = 0;
+            for result in receiver.iter() {
+                let index = result.index;
+                pending[index] = Some(result);
+                while let Some(result) = pending.get_mut(next).and_then(Option::take) {
+                    let tile = result.tile;
+                    for (k, pixel) in result.pixels.iter().enumerate() {
+                        if let Some((shaded, pixel_spp)) = pixel {
+                            let i = tile.x + k as u32 % tile.width;
+                            let j = tile.y + k as u32 / tile.width;
+                            acc.add(i, j, shaded, *pixel_spp);
+                        }
+                    }
+                    next += 1;
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,564 @@
+use std::str::FromStr;
+
+use crate::accumulator::AdaptiveSampling;
+use crate::aov::Aov;
+use crate::checkpoint::Checkpoint;
+use crate::denoise::Denoiser;
+use crate::environment::Environment;
+use crate::film::{ev100, Film};
+use crate::framebuffer::Output;
+use crate::integrator::{self, Integrator, PathTracer};
+use crate::intrinsics::{Distortion, Intrinsics};
+use crate::lens::LensSystem;
+use crate::motion::Motion;
+use crate::passes::Pass;
+use crate::punctual::PunctualLight;
+use crate::sampler::SamplerKind;
+use crate::scene::Scene;
+use crate::scheduler::{Scheduler, TileOrder};
+use crate::sky::{self, Sky};
+use crate::stereo::{Stereo, StereoLayout};
+use crate::tonemap::ToneMap;
+use crate::vector::{Mat4, Vec3};
+
+//render options, overridable from the command line with key=value arguments
+pub struct Settings {
+    pub scene: Scene,
+    pub width: u32,
+    pub height: u32,
+    pub integrator: Box<dyn Integrator>,
+    pub samples_per_pixel: u32,
+    pub scheduler: Scheduler,
+    pub pass_spp: u32,
+    pub save_interval: Option<f64>, //seconds between saves of the current estimate
+    pub save_passes: Option<u32>,   //passes between saves of the current estimate
+    pub time_budget: Option<f64>,   //seconds, replacing the spp count
+    pub adaptive: Option<AdaptiveSampling>,
+    pub seed: u64,
+    pub sampler: SamplerKind,
+    pub outputs: Vec<Output>,
+    pub stereo: Option<Stereo>,
+    pub film: Film,
+    pub lens: Option<LensSystem>,
+    pub focus_distance: Option<f64>,
+    pub intrinsics: Option<Intrinsics>,
+    pub extrinsic: Option<Mat4>,
+    pub shutter_interval: (f64, f64),
+    pub camera_motion: Option<Motion>,
+    pub aovs: Vec<Aov>,
+    pub passes: Vec<Pass>,
+    pub denoiser: Option<Denoiser>,
+    pub checkpoint: Option<String>, //written along with the outputs
+    pub resume: Option<Checkpoint>,
+    pub args: Vec<String>, //the options in effect, saved in checkpoints
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            scene: Scene::cornell_box(),
+            width: 1000,
+            height: 1000,
+            integrator: Box::new(PathTracer::default()),
+            samples_per_pixel: 2000,
+            scheduler: Scheduler {
+                threads: std::thread::available_parallelism().map_or(8, |n| n.get() as u32),
+                tile_size: 32,
+                order: TileOrder::Spiral,
+            },
+            pass_spp: 16,
+            save_interval: None,
+            save_passes: None,
+            time_budget: None,
+            adaptive: None,
+            seed: 0,
+            sampler: SamplerKind::Sobol,
+            outputs: vec![Output {
+                path: String::from("./renders/render.png"),
+                tonemap: None,
+                exposure: 0.0,
+                exr_half: false,
+            }],
+            stereo: None,
+            film: Film::default(),
+            lens: None,
+            focus_distance: None,
+            intrinsics: None,
+            extrinsic: None,
+            shutter_interval: (0.0, 0.0),
+            camera_motion: None,
+            aovs: vec![],
+            passes: vec![],
+            denoiser: None,
+            checkpoint: None,
+            resume: None,
+            args: vec![],
+        }
+    }
+}
+
+fn parse<T: FromStr>(key: &str, value: &str) -> T {
+    value
+        .parse()
+        .unwrap_or_else(|_| panic!("invalid value {} for option {}", value, key))
+}
+
+//comma separated list of exactly len numbers, or of at least min_len when min_len < len
+fn parse_list(key: &str, value: &str, min_len: usize, len: usize) -> Vec<f64> {
+    let values: Vec<f64> = value.split(',').map(|v| parse(key, v.trim())).collect();
+    if values.len() < min_len || values.len() > len {
+        panic!("option {} expects {} comma separated values", key, len);
+    }
+    values
+}
+
+//shutter speeds are accepted either in seconds or as a fraction, like 1/125
+fn parse_shutter(key: &str, value: &str) -> f64 {
+    match value.split_once('/') {
+        Some((num, den)) => parse::<f64>(key, num) / parse::<f64>(key, den),
+        None => parse(key, value),
+    }
+}
+
+//a punctual light given by values, followed by its intensity (or illuminance) either as one value
+//or as r,g,b
+fn parse_light(key: &str, value: &str, values: usize) -> (Vec<f64>, Vec3) {
+    let mut v = parse_list(key, value, values + 1, values + 3);
+    let intensity = match v.split_off(values)[..] {
+        [gray] => Vec3::ONE.scale(gray),
+        [r, g, b] => Vec3 { x: r, y: g, z: b },
+        _ => panic!("option {} expects one intensity or three (r,g,b)", key),
+    };
+    (v, intensity)
+}
+
+fn vec3(v: &[f64]) -> Vec3 {
+    Vec3 {
+        x: v[0],
+        y: v[1],
+        z: v[2],
+    }
+}
+
+//day of the year (0 for january 1st) of a YYYY-MM-DD date
+fn parse_date(key: &str, value: &str) -> f64 {
+    const MONTH_STARTS: [u32; 12] = [0, 31, 59, 90, 120, 151, 181, 212, 243, 273, 304, 334];
+    let parts: Vec<u32> = value.split('-').map(|v| parse(key, v)).collect();
+    let [year, month, day] = parts[..] else {
+        panic!("option {} expects a YYYY-MM-DD date", key);
+    };
+    if !(1..=12).contains(&month) || day == 0 {
+        panic!("invalid date {} for option {}", value, key);
+    }
+    let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    let leap_day = (leap && month > 2) as u32;
+    (MONTH_STARTS[month as usize - 1] + leap_day + day - 1) as f64
+}
+
+//hours, either fractional or as HH:MM
+fn parse_time(key: &str, value: &str) -> f64 {
+    match value.split_once(':') {
+        Some((hours, minutes)) => parse::<f64>(key, hours) + parse::<f64>(key, minutes) / 60.0,
+        None => parse(key, value),
+    }
+}
+
+//options that can be changed when resuming from a checkpoint
+const RESUMABLE_OPTIONS: [&str; 20] = [
+    "tile_size",
+    "tile_order",
+    "spp",
+    "noise_threshold",
+    "min_spp",
+    "pass_spp",
+    "time_budget",
+    "threads",
+    "save_interval",
+    "save_passes",
+    "checkpoint",
+    "output",
+    "exr",
+    "tonemap",
+    "white_point",
+    "display_exposure",
+    "aovs",
+    "denoise",
+    "denoise_strength",
+    "resume",
+];
+
+impl Settings {
+    pub fn from_args() -> Settings {
+        let mut settings = Settings::default();
+
+        let mut stereo_layout = None;
+        let mut interocular = 0.064;
+        let mut convergence = 1.0;
+
+        let mut ev = None;
+        let mut iso = None;
+        let mut shutter = None;
+        let mut aperture = None;
+        let mut white_balance = None;
+        let mut vignetting = 0.0;
+
+        let mut lens_path = None;
+        let mut sensor_width = 36.0;
+
+        let mut intrinsics = None;
+        let mut distortion = Distortion::default();
+
+        let mut shutter_interval = None;
+
+        let mut outputs = None;
+        let mut exr_half = false;
+        let mut tonemap = None;
+        let mut white_point = 4.0;
+        let mut display_exposure = 0.0;
+
+        let mut integrator = String::from("path");
+        let mut options = integrator::Options::default();
+
+        let mut environment = None;
+        let mut environment_rotation = 0.0;
+        let mut environment_intensity = 1.0;
+        let mut sun_direction = Vec3 {
+            x: 1.0,
+            y: 1.0,
+            z: 1.0,
+        };
+        let mut sun_location = None;
+        let mut sun_date = 172.0; //june 21st, 2024
+        let mut sun_time = 12.0;
+        let mut turbidity = 3.0;
+        let mut ground_albedo = 0.3;
+        let mut punctual_lights = vec![];
+
+        let mut noise_threshold = None;
+        let mut min_spp = 16;
+
+        let mut denoise = None;
+        let mut denoise_strength = 1.0;
+
+        //a resumed render keeps the options of the checkpoint, only those that don't change
+        //the samples can be given again
+        let mut args: Vec<String> = std::env::args().skip(1).collect();
+        if let Some(index) = args.iter().position(|arg| arg.starts_with("resume=")) {
+            let path = args.remove(index)["resume=".len()..].to_string();
+            let checkpoint = Checkpoint::load(&path)
+                .unwrap_or_else(|e| panic!("couldn't load checkpoint {} : {}", path, e));
+            for arg in &args {
+                let key = arg.split('=').next().unwrap_or("");
+                if !RESUMABLE_OPTIONS.contains(&key) && !checkpoint.args.contains(arg) {
+                    panic!("option {} can't be changed when resuming a render", key);
+                }
+            }
+            args = [checkpoint.args.clone(), args].concat();
+            settings.resume = Some(checkpoint);
+        }
+        settings.args = args.clone();
+
+        for arg in args {
+            let (key, value) = match arg.split_once('=') {
+                Some(pair) => pair,
+                None => {
+                    println!("WARNING : ignoring argument {}, expected key=value", arg);
+                    continue;
+                }
+            };
+
+            match key {
+                "scene" => {
+                    settings.scene =
+                        Scene::from_name(value).unwrap_or_else(|| panic!("unknown scene {}", value))
+                }
+                "environment" => environment = Some(String::from(value)),
+                "environment_rotation" => environment_rotation = parse(key, value),
+                "environment_intensity" => environment_intensity = parse(key, value),
+                "sun_direction" => sun_direction = vec3(&parse_list(key, value, 3, 3)),
+                "sun_location" => sun_location = Some(parse_list(key, value, 2, 2)),
+                "sun_date" => sun_date = parse_date(key, value),
+                "sun_time" => sun_time = parse_time(key, value),
+                "point_light" => {
+                    let (v, intensity) = parse_light(key, value, 3);
+                    punctual_lights.push(PunctualLight::Point {
+                        pos: vec3(&v),
+                        intensity,
+                    })
+                }
+                "spot_light" => {
+                    let (v, intensity) = parse_light(key, value, 8);
+                    punctual_lights.push(PunctualLight::Spot {
+                        pos: vec3(&v),
+                        dir: vec3(&v[3..]).normalized(),
+                        intensity,
+                        cos_inner: v[6].to_radians().cos(),
+                        cos_outer: v[7].to_radians().cos(),
+                    })
+                }
+                "distant_light" => {
+                    let (v, illuminance) = parse_light(key, value, 4);
+                    punctual_lights.push(PunctualLight::Distant {
+                        dir: vec3(&v).normalized(),
+                        illuminance,
+                        cos_radius: (v[3] / 2.0).to_radians().cos(),
+                    })
+                }
+                "turbidity" => turbidity = parse(key, value),
+                "ground_albedo" => ground_albedo = parse(key, value),
+                "width" => settings.width = parse(key, value),
+                "height" => settings.height = parse(key, value),
+                "integrator" => integrator = String::from(value),
+                "bounces" => options.path_tracer.bounces = parse(key, value),
+                "roulette_depth" => options.path_tracer.roulette_depth = parse(key, value),
+                "ao_distance" => options.ao_distance = parse(key, value),
+                "photons" => options.photons = parse(key, value),
+                "photon_radius" => options.photon_radius = parse(key, value),
+                "mutations" => options.mutations = parse(key, value),
+                "bootstrap" => options.bootstrap = parse(key, value),
+                "spp" => settings.samples_per_pixel = parse(key, value),
+                "threads" => settings.scheduler.threads = parse(key, value),
+                "tile_size" => settings.scheduler.tile_size = parse(key, value),
+                "tile_order" => {
+                    settings.scheduler.order = TileOrder::from_name(value)
+                        .unwrap_or_else(|| panic!("unknown tile order {}", value))
+                }
+                "pass_spp" => {
+                    settings.pass_spp = parse(key, value);
+                    if settings.pass_spp == 0 {
+                        panic!("pass_spp must be at least 1");
+                    }
+                }
+                "save_interval" => settings.save_interval = Some(parse(key, value)),
+                "save_passes" => settings.save_passes = Some(parse(key, value)),
+                "time_budget" => settings.time_budget = Some(parse(key, value)),
+                "noise_threshold" => noise_threshold = Some(parse(key, value)),
+                "min_spp" => min_spp = parse(key, value),
+                "seed" => settings.seed = parse(key, value),
+                "sampler" => {
+                    settings.sampler = SamplerKind::from_name(value)
+                        .unwrap_or_else(|| panic!("unknown sampler {}", value))
+                }
+                "checkpoint" => settings.checkpoint = Some(String::from(value)),
+                "output" => outputs = Some(String::from(value)),
+                "exr" => {
+                    exr_half = match value {
+                        "half" => true,
+                        "float" => false,
+                        _ => panic!("exr precision must be half or float"),
+                    }
+                }
+                "tonemap" => tonemap = Some(String::from(value)),
+                "white_point" => white_point = parse(key, value),
+                "display_exposure" => display_exposure = parse(key, value),
+                "aovs" => {
+                    settings.aovs = match value {
+                        "all" => Aov::ALL.to_vec(),
+                        _ => value
+                            .split(',')
+                            .map(|name| {
+                                Aov::from_name(name)
+                                    .unwrap_or_else(|| panic!("unknown aov {}", name))
+                            })
+                            .collect(),
+                    }
+                }
+                "passes" => {
+                    //all stands for the default passes, and can be mixed with custom ones
+                    settings.passes = value
+                        .split(',')
+                        .flat_map(|spec| match spec {
+                            "all" => Pass::defaults(),
+                            _ => vec![Pass::from_spec(spec)
+                                .unwrap_or_else(|| panic!("invalid pass {}", spec))],
+                        })
+                        .collect()
+                }
+                "denoise" => {
+                    denoise = match value {
+                        "off" => None,
+                        "on" => Some(false),
+                        "both" => Some(true),
+                        _ => panic!("denoise must be off, on or both"),
+                    }
+                }
+                "denoise_strength" => {
+                    denoise_strength = parse(key, value);
+                    if denoise_strength <= 0.0 {
+                        panic!("denoise_strength must be positive");
+                    }
+                }
+                "stereo" => {
+                    stereo_layout = Some(
+                        StereoLayout::from_name(value)
+                            .unwrap_or_else(|| panic!("unknown stereo layout {}", value)),
+                    )
+                }
+                "interocular" => interocular = parse(key, value),
+                "convergence" => convergence = parse(key, value),
+                "ev" => ev = Some(parse(key, value)),
+                "iso" => iso = Some(parse(key, value)),
+                "shutter" => shutter = Some(parse_shutter(key, value)),
+                "aperture" => aperture = Some(parse(key, value)),
+                "white_balance" => white_balance = Some(parse(key, value)),
+                "vignetting" => vignetting = parse(key, value),
+                "lens" => lens_path = Some(String::from(value)),
+                "sensor" => sensor_width = parse(key, value),
+                "focus_distance" => settings.focus_distance = Some(parse(key, value)),
+                "intrinsics" => intrinsics = Some(parse_list(key, value, 4, 4)),
+                "distortion" => {
+                    let k = parse_list(key, value, 4, 5);
+                    distortion = Distortion {
+                        k1: k[0],
+                        k2: k[1],
+                        p1: k[2],
+                        p2: k[3],
+                        k3: k.get(4).cloned().unwrap_or(0.0),
+                    };
+                }
+                "shutter_interval" => {
+                    let t = parse_list(key, value, 2, 2);
+                    shutter_interval = Some((t[0], t[1]));
+                }
+                "camera_velocity" => {
+                    let v = parse_list(key, value, 3, 3);
+                    settings.camera_motion = Some(Motion::Linear(Vec3 {
+                        x: v[0],
+                        y: v[1],
+                        z: v[2],
+                    }));
+                }
+                "extrinsic" => {
+                    let m = parse_list(key, value, 16, 16);
+                    let mut elems = [[0.0; 4]; 4];
+                    for (i, row) in elems.iter_mut().enumerate() {
+                        row.copy_from_slice(&m[4 * i..4 * i + 4]);
+                    }
+                    settings.extrinsic = Some(Mat4 { elems });
+                }
+                _ => println!("WARNING : unknown option {}, ignoring it", key),
+            }
+        }
+
+        //the sky is placed by the sun, either given directly or from where and when it's seen
+        let sun = match sun_location {
+            Some(location) => sky::sun_direction(location[0], location[1], sun_date, sun_time),
+            None => sun_direction.normalized(),
+        };
+        settings.scene.environment = environment.map(|path| {
+            if path == "sky" {
+                if sun.y <= 0.0 {
+                    println!("WARNING : the sun is below the horizon, the sky only covers the day");
+                }
+                Sky {
+                    sun,
+                    turbidity,
+                    ground_albedo,
+                }
+                .environment(environment_intensity)
+            } else {
+                Environment::load(&path, environment_rotation, environment_intensity)
+                    .unwrap_or_else(|e| panic!("couldn't load environment {} : {}", path, e))
+            }
+        });
+        settings.scene.punctual_lights = punctual_lights;
+        if integrator == "bdpt" || integrator == "sppm" {
+            if settings.scene.environment.is_some() {
+                println!("WARNING : the environment only lights the path and mlt integrators");
+            }
+            if !settings.scene.punctual_lights.is_empty() {
+                println!("WARNING : punctual lights only light the path and mlt integrators");
+            }
+        }
+
+        settings.integrator = integrator::from_name(&integrator, options, &settings.scene)
+            .unwrap_or_else(|| panic!("unknown integrator {}", integrator));
+        if lens_path.is_some() && intrinsics.is_some() {
+            panic!("the lens and intrinsics options can't be used together");
+        }
+        if stereo_layout.is_some() && intrinsics.is_some() {
+            panic!("the intrinsics match the whole image, they can't be used with stereo");
+        }
+        if integrator == "bdpt"
+            && (lens_path.is_some() || intrinsics.is_some() || stereo_layout.is_some())
+        {
+            panic!("bdpt only supports the pinhole camera");
+        }
+        if integrator != "path" && !settings.passes.is_empty() {
+            println!("WARNING : passes are only computed by the path integrator");
+        }
+        if integrator == "mlt" && noise_threshold.is_some() {
+            panic!("mlt samples land in any pixel, adaptive sampling can't follow them");
+        }
+        settings.adaptive =
+            noise_threshold.map(|threshold| AdaptiveSampling { threshold, min_spp });
+        settings.denoiser = denoise.map(|keep_raw| Denoiser::new(denoise_strength, keep_raw));
+
+        settings.stereo = stereo_layout.map(|layout| Stereo {
+            layout,
+            interocular,
+            convergence,
+        });
+
+        //an explicit ev wins, otherwise any of iso, shutter and aperture sets the exposure,
+        //the others defaulting to ISO 100, 1s and f/1 (EV 0, about the brightness of the
+        //unexposed film with the emissive values used in scene.rs)
+        let ev = ev.or_else(|| {
+            if iso.is_none() && shutter.is_none() && aperture.is_none() {
+                None
+            } else {
+                Some(ev100(
+                    iso.unwrap_or(100.0),
+                    shutter.unwrap_or(1.0),
+                    aperture.unwrap_or(1.0),
+                ))
+            }
+        });
+        settings.film = Film::new(ev, white_balance, vignetting);
+
+        //motion blur covers the exposure time unless told otherwise
+        settings.shutter_interval = shutter_interval
+            .or_else(|| shutter.map(|shutter| (0.0, shutter)))
+            .unwrap_or((0.0, 0.0));
+
+        settings.lens = lens_path.map(|path| {
+            LensSystem::load(&path, sensor_width)
+                .unwrap_or_else(|e| panic!("couldn't load lens {} : {}", path, e))
+        });
+
+        //the calibrated image has the size of the render, so that they match pixel for pixel
+        settings.intrinsics = intrinsics.map(|k| Intrinsics {
+            fx: k[0],
+            fy: k[1],
+            cx: k[2],
+            cy: k[3],
+            distortion,
+            width: settings.width,
+            height: settings.height,
+        });
+
+        //each output is path[@tonemap], the tone mapping defaulting to the tonemap option
+        let parse_tonemap = |name: &str| {
+            ToneMap::from_name(name, white_point)
+                .unwrap_or_else(|| panic!("unknown tone mapping {}", name))
+        };
+        let outputs = outputs.unwrap_or_else(|| settings.outputs[0].path.clone());
+        settings.outputs = outputs
+            .split(',')
+            .map(|spec| {
+                let (path, tonemap) = match spec.split_once('@') {
+                    Some((path, name)) => (path, Some(parse_tonemap(name))),
+                    None => (spec, tonemap.as_deref().map(parse_tonemap)),
+                };
+                Output {
+                    path: String::from(path),
+                    tonemap,
+                    exposure: display_exposure,
+                    exr_half,
+                }
+            })
+            .collect();
+
+        settings
+    }
+}
@@ -0,0 +1,193 @@
+use std::f64::consts::PI;
+
+use crate::environment::{Environment, Sun};
+use crate::vector::Vec3;
+
+//size of the image the sky is held in, it's smooth enough for the sun to be the only detail
+const SKY_WIDTH: usize = 512;
+const SKY_HEIGHT: usize = 256;
+
+//illuminance of the sun above the atmosphere (lux), and its angular radius
+const SOLAR_ILLUMINANCE: f64 = 128_000.0;
+const SUN_RADIUS: f64 = 0.004_65;
+
+//wavelengths (in micrometers) the transmittance of the atmosphere is taken at for red, green
+//and blue
+const WAVELENGTHS: [f64; 3] = [0.65, 0.55, 0.45];
+
+//the coefficients A to E of the Perez distribution of the luminance Y and the chromaticities x
+//and y, as linear functions (slope, intercept) of the turbidity
+const PEREZ_LUMINANCE: [(f64, f64); 5] = [
+    (0.1787, -1.4630),
+    (-0.3554, 0.4275),
+    (-0.0227, 5.3251),
+    (0.1206, -2.5771),
+    (-0.0670, 0.3703),
+];
+const PEREZ_X: [(f64, f64); 5] = [
+    (-0.0193, -0.2592),
+    (-0.0665, 0.0008),
+    (-0.0004, 0.2125),
+    (-0.0641, -0.8989),
+    (-0.0033, 0.0452),
+];
+const PEREZ_Y: [(f64, f64); 5] = [
+    (-0.0167, -0.2608),
+    (-0.0950, 0.0092),
+    (-0.0079, 0.2102),
+    (-0.0441, -1.6537),
+    (-0.0109, 0.0529),
+];
+
+//the daylight sky of Preetham et al. (1999), with the sun and a diffuse ground reflecting them.
+//Radiance is in cd/m², a clear day being exposed with ev around 15
+pub struct Sky {
+    pub sun: Vec3, //direction toward the sun
+    pub turbidity: f64,
+    pub ground_albedo: f64,
+}
+
+//direction toward the sun from latitude and longitude (in degrees, north and east being positive)
+//on the given day of the year, at the given hour (UTC, fractional). North is -z and east +x
+pub fn sun_direction(latitude: f64, longitude: f64, day: f64, hour: f64) -> Vec3 {
+    let declination = -23.44f64.to_radians() * (2.0 * PI * (day + 10.0) / 365.0).cos();
+    //equation of time, in minutes
+    let b = 2.0 * PI * (day - 81.0) / 365.0;
+    let equation = 9.87 * (2.0 * b).sin() - 7.53 * b.cos() - 1.5 * b.sin();
+    let solar_time = hour + longitude / 15.0 + equation / 60.0;
+    let hour_angle = (15.0 * (solar_time - 12.0)).to_radians();
+
+    let latitude = latitude.to_radians();
+    let sin_elevation =
+        latitude.sin() * declination.sin() + latitude.cos() * declination.cos() * hour_angle.cos();
+    let cos_elevation = (1.0 - sin_elevation * sin_elevation).max(0.0).sqrt();
+    //azimuth from north toward east
+    let cos_azimuth = ((declination.sin() - sin_elevation * latitude.sin())
+        / (cos_elevation * latitude.cos()).max(1e-9))
+    .clamp(-1.0, 1.0);
+    let azimuth = if hour_angle.sin() > 0.0 {
+        2.0 * PI - cos_azimuth.acos()
+    } else {
+        cos_azimuth.acos()
+    };
+    Vec3 {
+        x: cos_elevation * azimuth.sin(),
+        y: sin_elevation,
+        z: -cos_elevation * azimuth.cos(),
+    }
+}
+
+fn perez(coefficients: &[(f64, f64); 5], turbidity: f64, cos_theta: f64, gamma: f64) -> f64 {
+    let [a, b, c, d, e] = coefficients.map(|(slope, intercept)| slope * turbidity + intercept);
+    (1.0 + a * (b / cos_theta.max(1e-4)).exp())
+        * (1.0 + c * (d * gamma).exp() + e * gamma.cos().powi(2))
+}
+
+//linear sRGB of the chromaticity x, y with luminance lum
+fn xy_to_rgb(x: f64, y: f64, lum: f64) -> Vec3 {
+    let (big_x, big_z) = (x * lum / y, (1.0 - x - y) * lum / y);
+    Vec3 {
+        x: 3.2404542 * big_x - 1.5371385 * lum - 0.4985314 * big_z,
+        y: -0.9692660 * big_x + 1.8760108 * lum + 0.0415560 * big_z,
+        z: 0.0556434 * big_x - 0.2040259 * lum + 1.0572252 * big_z,
+    }
+    .apply_to_each(|c| c.max(0.0))
+}
+
+impl Sky {
+    //zenith angle of the sun, the model only covering the day
+    fn sun_theta(&self) -> f64 {
+        self.sun.y.clamp(0.0, 1.0).acos()
+    }
+
+    //radiance of the sky (without the sun) above the horizon
+    fn radiance(&self, dir: &Vec3) -> Vec3 {
+        let t = self.turbidity;
+        let theta_s = self.sun_theta();
+        let gamma = dir.dot(&self.sun).clamp(-1.0, 1.0).acos();
+
+        //luminance (in kcd/m²) and chromaticity at the zenith
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_s);
+        let zenith_lum = ((4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192).max(0.0);
+        let (th, th2, th3) = (theta_s, theta_s * theta_s, theta_s.powi(3));
+        let zenith_x = t * t * (0.00166 * th3 - 0.00375 * th2 + 0.00209 * th)
+            + t * (-0.02903 * th3 + 0.06377 * th2 - 0.03202 * th + 0.00394)
+            + (0.11693 * th3 - 0.21196 * th2 + 0.06052 * th + 0.25886);
+        let zenith_y = t * t * (0.00275 * th3 - 0.00610 * th2 + 0.00317 * th)
+            + t * (-0.04214 * th3 + 0.08970 * th2 - 0.04153 * th + 0.00516)
+            + (0.15346 * th3 - 0.26756 * th2 + 0.06670 * th + 0.26688);
+
+        //the distributions relative to the zenith
+        let relative = |coefficients| {
+            perez(coefficients, t, dir.y, gamma) / perez(coefficients, t, 1.0, theta_s)
+        };
+        xy_to_rgb(
+            zenith_x * relative(&PEREZ_X),
+            zenith_y * relative(&PEREZ_Y),
+            1000.0 * zenith_lum * relative(&PEREZ_LUMINANCE),
+        )
+    }
+
+    //the sun seen through the air mass in its direction, scattered by molecules (Rayleigh) and
+    //aerosols (Angstrom's formula, their amount growing with the turbidity)
+    fn sun(&self) -> Sun {
+        let theta = self.sun_theta();
+        let air_mass =
+            1.0 / (theta.cos() + 0.15 * (93.885 - theta.to_degrees()).powf(-1.253)).max(1e-3);
+        let beta = 0.04608 * self.turbidity - 0.04586;
+        let [r, g, b] = WAVELENGTHS.map(|lambda: f64| {
+            let rayleigh = -0.008735 * lambda.powf(-4.08);
+            let aerosol = -beta * lambda.powf(-1.3);
+            ((rayleigh + aerosol) * air_mass).exp()
+        });
+        let solid_angle = 2.0 * PI * (1.0 - SUN_RADIUS.cos());
+        Sun {
+            dir: self.sun.clone(),
+            radiance: Vec3 { x: r, y: g, z: b }.scale(SOLAR_ILLUMINANCE / solid_angle),
+            cos_radius: SUN_RADIUS.cos(),
+        }
+    }
+
+    //the sky above the horizon and below it the ground, diffusely reflecting the sky and the sun
+    pub fn environment(&self, intensity: f64) -> Environment {
+        let sun = (self.sun.y > 0.0).then(|| self.sun());
+
+        //irradiance of the ground, summed over the rows and columns of the upper hemisphere
+        let (columns, rows) = (SKY_WIDTH, SKY_HEIGHT / 2);
+        let mut irradiance = Vec3::ZERO;
+        for row in 0..rows {
+            let theta = PI / 2.0 * (row as f64 + 0.5) / rows as f64;
+            let solid_angle = (2.0 * PI / columns as f64) * (PI / 2.0 / rows as f64) * theta.sin();
+            for column in 0..columns {
+                let phi = 2.0 * PI * (column as f64 + 0.5) / columns as f64;
+                let dir = Vec3 {
+                    x: theta.sin() * phi.sin(),
+                    y: theta.cos(),
+                    z: -theta.sin() * phi.cos(),
+                };
+                irradiance = &irradiance + &self.radiance(&dir).scale(theta.cos() * solid_angle);
+            }
+        }
+        if let Some(sun) = &sun {
+            irradiance = &irradiance + &sun.radiance.scale(self.sun.y * sun.solid_angle());
+        }
+        let ground = irradiance.scale(self.ground_albedo / PI);
+
+        Environment::from_fn(
+            format!(
+                "sky, sun {:?}, turbidity {}, ground albedo {}, intensity {}",
+                self.sun, self.turbidity, self.ground_albedo, intensity
+            ),
+            (SKY_WIDTH, SKY_HEIGHT),
+            |dir| {
+                if dir.y > 0.0 {
+                    self.radiance(dir)
+                } else {
+                    ground.clone()
+                }
+            },
+            intensity,
+            sun,
+        )
+    }
+}
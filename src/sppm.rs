@@ -0,0 +1,300 @@
+use std::f64::consts::PI;
+use std::sync::{Arc, Mutex, OnceLock};
+
+use crate::bsdf::{bsdf, light_weight, roulette, scatter, spawn_ray};
+use crate::integrator::{Integrator, PathTracer, Splat};
+use crate::lights::Lights;
+use crate::render::{intersect, Context, Intersection, Ray};
+use crate::sampler::Sampler;
+use crate::scene::Scene;
+use crate::vector::Vec3;
+
+//share of the photons of a map that the next one keeps, which sets how fast the radius shrinks
+const ALPHA: f64 = 2.0 / 3.0;
+//photon maps kept around, as a pass over a pixel needs one per sample
+const CACHED_MAPS: usize = 32;
+
+//a photon map, empty until the first thread needing it has built it
+type MapSlot = Arc<OnceLock<Arc<PhotonMap>>>;
+
+//stochastic progressive photon mapping, in its probabilistic form : the n-th sample of every pixel
+//follows the camera path through delta bounces to a visible point, where it gathers the n-th
+//photon map. Every map is traced with photons of its own and gathered within a radius shrinking
+//with n, so that the average of the samples converges, even along the specular-diffuse-specular
+//paths the path tracer can't find
+pub struct Sppm {
+    bounces: u8,
+    roulette_depth: u8,
+    photons: u32, //per map
+    radius: f64,  //of the first map
+    lights: Lights,
+    maps: Mutex<Vec<(u32, MapSlot)>>, //the last maps used and their index
+}
+
+struct Photon {
+    pos: Vec3,
+    dir: Vec3, //it was travelling along
+    power: Vec3,
+}
+
+//photons hashed by the cell of a grid of the gather radius they fall in, the photons of a bucket
+//being contiguous in order
+struct PhotonMap {
+    radius: f64,
+    photons: Vec<Photon>,
+    starts: Vec<usize>, //index in order of the first photon of each bucket, and of the end
+    order: Vec<usize>,
+}
+
+impl PhotonMap {
+    fn new(photons: Vec<Photon>, radius: f64) -> PhotonMap {
+        let buckets = photons.len().max(1);
+        let mut map = PhotonMap {
+            radius,
+            photons,
+            starts: vec![0; buckets + 1],
+            order: vec![],
+        };
+        let keys: Vec<usize> = map
+            .photons
+            .iter()
+            .map(|photon| map.bucket(map.cell(&photon.pos)))
+            .collect();
+
+        //counting sort of the photons by bucket
+        for &key in &keys {
+            map.starts[key + 1] += 1;
+        }
+        for bucket in 0..buckets {
+            map.starts[bucket + 1] += map.starts[bucket];
+        }
+        let mut next = map.starts.clone();
+        map.order = vec![0; keys.len()];
+        for (index, &key) in keys.iter().enumerate() {
+            map.order[next[key]] = index;
+            next[key] += 1;
+        }
+        map
+    }
+
+    fn cell(&self, pos: &Vec3) -> (i64, i64, i64) {
+        (
+            (pos.x / self.radius).floor() as i64,
+            (pos.y / self.radius).floor() as i64,
+            (pos.z / self.radius).floor() as i64,
+        )
+    }
+
+    fn bucket(&self, (x, y, z): (i64, i64, i64)) -> usize {
+        let hash = x.wrapping_mul(73856093) ^ y.wrapping_mul(19349663) ^ z.wrapping_mul(83492791);
+        (hash as u64 % (self.starts.len() as u64 - 1)) as usize
+    }
+
+    //calls f on the photons within the radius of pos, looking in the cells around it
+    fn gather(&self, pos: &Vec3, mut f: impl FnMut(&Photon)) {
+        let (x, y, z) = self.cell(pos);
+        let mut visited = Vec::with_capacity(27);
+        for dx in -1..=1 {
+            for dy in -1..=1 {
+                for dz in -1..=1 {
+                    //cells sharing a bucket are only looked at once
+                    let bucket = self.bucket((x + dx, y + dy, z + dz));
+                    if visited.contains(&bucket) {
+                        continue;
+                    }
+                    visited.push(bucket);
+                    for &index in &self.order[self.starts[bucket]..self.starts[bucket + 1]] {
+                        let photon = &self.photons[index];
+                        let d = photon.pos.minus(pos);
+                        if d.dot(&d) < self.radius * self.radius {
+                            f(photon);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Sppm {
+    pub fn new(scene: &Scene, path_tracer: &PathTracer, photons: u32, radius: f64) -> Sppm {
+        Sppm {
+            bounces: path_tracer.bounces,
+            roulette_depth: path_tracer.roulette_depth,
+            photons,
+            radius,
+            lights: Lights::new(scene),
+            maps: Mutex::new(vec![]),
+        }
+    }
+
+    //the radius of the n-th map : each map keeps a share ALPHA of the photons the previous one
+    //gathered, so that the bias vanishes while the variance grows slowly enough to average out
+    fn radius(&self, map: u32) -> f64 {
+        let shrink: f64 = (1..=map)
+            .map(|i| (i as f64 + ALPHA) / (i as f64 + 1.0))
+            .product();
+        self.radius * shrink.sqrt()
+    }
+
+    //the first thread needing a map builds it while the others needing it wait, without holding
+    //the lock that threads using other maps go through
+    fn photon_map(&self, ctx: &Context, map: u32) -> Arc<PhotonMap> {
+        let slot = {
+            let mut maps = self.maps.lock().unwrap();
+            match maps.iter().find(|(index, _)| *index == map) {
+                Some((_, slot)) => slot.clone(),
+                None => {
+                    if maps.len() == CACHED_MAPS {
+                        let oldest = (0..maps.len()).min_by_key(|&k| maps[k].0).unwrap();
+                        maps.swap_remove(oldest);
+                    }
+                    let slot = Arc::new(OnceLock::new());
+                    maps.push((map, slot.clone()));
+                    slot
+                }
+            }
+        };
+        slot.get_or_init(|| {
+            Arc::new(PhotonMap::new(
+                self.trace_photons(ctx, map),
+                self.radius(map),
+            ))
+        })
+        .clone()
+    }
+
+    //photons are left on every surface with a non delta lobe they bounce on
+    fn trace_photons(&self, ctx: &Context, map: u32) -> Vec<Photon> {
+        let (open, close) = ctx.shutter_interval;
+        let mut photons = vec![];
+        let mut sampler = ctx.sampler.clone();
+        for index in 0..self.photons {
+            sampler.start_global_sample(map, index);
+            let time = open + (close - open) * sampler.get_1d();
+            sampler.start_bounce(0);
+            let u_light = sampler.get_1d();
+            let (u_pos, u_dir) = (sampler.get_2d(), sampler.get_2d());
+            let Some(emission) = self.lights.sample(&ctx.scene, u_light, u_pos, u_dir) else {
+                break;
+            };
+            if emission.pdf_dir == 0.0 {
+                continue;
+            }
+            let cos = emission.normal.dot(&emission.dir);
+            let mut power = ctx.scene.objects[emission.object]
+                .material()
+                .emissive
+                .scale(cos.abs() / (emission.pdf_pos * emission.pdf_dir));
+            let mut ray = spawn_ray(&emission.pos, &emission.normal, emission.dir, time);
+
+            let mut throughput = Vec3::ONE;
+            for depth in 0..=self.bounces as u32 {
+                let int = intersect(&ray, &ctx.scene);
+                if !int.hit {
+                    break;
+                }
+                if int.mat.roughness > 0.0 {
+                    photons.push(Photon {
+                        pos: int.pos.clone(),
+                        dir: ray.dir.clone(),
+                        power: power.clone(),
+                    });
+                }
+
+                sampler.start_bounce(1 + depth);
+                let u_lobe = sampler.get_1d();
+                let u_refraction = sampler.get_1d();
+                let u_direction = sampler.get_2d();
+                let u_roulette = sampler.get_1d();
+                let scattered = scatter(
+                    int.mat,
+                    &int.normal,
+                    &ray,
+                    u_lobe,
+                    u_refraction,
+                    u_direction,
+                );
+                let weight = light_weight(int.mat, &int.normal, &ray, &scattered);
+                if weight.norm() == 0.0
+                    || !weight.norm().is_finite()
+                    || !scattered.dir.x.is_finite()
+                {
+                    break;
+                }
+                power = power.mult(&weight);
+                throughput = throughput.mult(&weight);
+
+                if depth + 1 > self.roulette_depth as u32 {
+                    let Some(survival) = roulette(&throughput, u_roulette) else {
+                        break;
+                    };
+                    power = power.scale(1.0 / survival);
+                    throughput = throughput.scale(1.0 / survival);
+                }
+
+                ray = spawn_ray(&int.pos, &int.normal, scattered.dir, time);
+                ray.n = scattered.next_n;
+            }
+        }
+        photons
+    }
+}
+
+impl Integrator for Sppm {
+    fn radiance<'a>(
+        &self,
+        ctx: &'a Context,
+        mut ray: Ray,
+        hit: Intersection<'a>,
+        sampler: &mut Sampler,
+        _passes: &mut [Vec3],
+        _splats: &mut Vec<Splat>,
+    ) -> Vec3 {
+        let map = self.photon_map(ctx, sampler.index());
+        let mut int = hit;
+        let mut beta = Vec3::ONE;
+        let mut radiance = Vec3::ZERO;
+        for depth in 0..=self.bounces as u32 {
+            if !int.hit {
+                break;
+            }
+            radiance = &radiance + &beta.mult(&int.mat.emissive);
+
+            sampler.start_bounce(depth);
+            let u_lobe = sampler.get_1d();
+            let u_refraction = sampler.get_1d();
+            let u_direction = sampler.get_2d();
+            let scattered = scatter(
+                int.mat,
+                &int.normal,
+                &ray,
+                u_lobe,
+                u_refraction,
+                u_direction,
+            );
+            if !scattered.delta {
+                //the photons around the visible point give the light its non delta lobe reflects,
+                //that lobe having been picked with probability prob
+                let mut reflected = Vec3::ZERO;
+                map.gather(&int.pos, |photon| {
+                    let f = bsdf(int.mat, &int.normal, &ray.dir, &photon.dir.scale(-1.0));
+                    reflected = &reflected + &f.mult(&photon.power);
+                });
+                let area = PI * map.radius * map.radius;
+                let scale = 1.0 / (area * self.photons as f64 * scattered.prob);
+                radiance = &radiance + &beta.mult(&reflected).scale(scale);
+                break;
+            }
+
+            beta = beta.mult(&scattered.weight);
+            if beta.norm() == 0.0 || !scattered.dir.x.is_finite() {
+                break;
+            }
+            ray = spawn_ray(&int.pos, &int.normal, scattered.dir, ray.time);
+            ray.n = scattered.next_n;
+            int = intersect(&ray, &ctx.scene);
+        }
+        radiance
+    }
+}
@@ -0,0 +1,122 @@
+use std::f64::consts::PI;
+
+use crate::render::{screen_coords, Camera, CameraSample, Ray};
+use crate::Vec3;
+
+pub enum StereoLayout {
+    SideBySide,      //left eye on the left half, right eye on the right half
+    OverUnder,       //left eye on the top half, right eye on the bottom half
+    Omnidirectional, //equirectangular omni-directional stereo, left eye on top
+}
+
+pub struct Stereo {
+    pub layout: StereoLayout,
+    pub interocular: f64,
+    pub convergence: f64, //distance of zero parallax, unused by the omnidirectional layout
+}
+
+impl StereoLayout {
+    pub fn from_name(name: &str) -> Option<StereoLayout> {
+        match name {
+            "side-by-side" | "sbs" => Some(StereoLayout::SideBySide),
+            "over-under" | "ou" => Some(StereoLayout::OverUnder),
+            "omnidirectional" | "ods" => Some(StereoLayout::Omnidirectional),
+            _ => None,
+        }
+    }
+}
+
+impl Stereo {
+    //the camera ray through pixel (i, j) of the whole frame, None if the lens blocks it
+    pub fn camera_ray(
+        &self,
+        camera: &Camera,
+        i: u32,
+        j: u32,
+        width: u32,
+        height: u32,
+        sample: &CameraSample,
+    ) -> Option<Ray> {
+        //the right eye (bottom one) gets the extra column (row) of odd sizes
+        let half_width = width / 2;
+        let half_height = height / 2;
+
+        match self.layout {
+            StereoLayout::SideBySide => {
+                let (eye, i, eye_width) = if i < half_width {
+                    (-0.5, i, half_width)
+                } else {
+                    (0.5, i - half_width, width - half_width)
+                };
+                let (x, y) = screen_coords(i, j, eye_width, height);
+                let aspect = eye_width as f64 / height as f64;
+                self.eye_ray(camera, eye, (x, y), aspect, sample)
+            }
+            StereoLayout::OverUnder => {
+                let (eye, j, eye_height) = if j < half_height {
+                    (-0.5, j, half_height)
+                } else {
+                    (0.5, j - half_height, height - half_height)
+                };
+                let (x, y) = screen_coords(i, j, width, eye_height);
+                let aspect = width as f64 / eye_height as f64;
+                self.eye_ray(camera, eye, (x, y), aspect, sample)
+            }
+            StereoLayout::Omnidirectional => {
+                let (eye, j, eye_height) = if j < half_height {
+                    (-0.5, j, half_height)
+                } else {
+                    (0.5, j - half_height, height - half_height)
+                };
+                let (x, y) = screen_coords(i, j, width, eye_height);
+                self.omnidirectional_ray(camera, eye, x, y, sample.time)
+            }
+        }
+    }
+
+    //eye is -0.5 for the left eye and 0.5 for the right one, aspect that of its image
+    fn eye_ray(
+        &self,
+        camera: &Camera,
+        eye: f64,
+        (x, y): (f64, f64),
+        aspect: f64,
+        sample: &CameraSample,
+    ) -> Option<Ray> {
+        camera
+            .eye(eye * self.interocular, self.convergence, aspect)
+            .ray(x, y, sample)
+    }
+
+    //x maps to longitude (-pi to pi, 0 facing the camera target), y to latitude (-pi/2 to pi/2).
+    //every ray starts on the circle of diameter interocular, tangent to it.
+    fn omnidirectional_ray(
+        &self,
+        camera: &Camera,
+        eye: f64,
+        x: f64,
+        y: f64,
+        time: f64,
+    ) -> Option<Ray> {
+        let theta = x * PI;
+        let phi = y * PI / 2.0;
+
+        let local_dir = Vec3 {
+            x: theta.sin() * phi.cos(),
+            y: phi.sin(),
+            z: -theta.cos() * phi.cos(),
+        };
+        let local_offset = Vec3 {
+            x: theta.cos(),
+            y: 0.0,
+            z: theta.sin(),
+        }
+        .scale(eye * self.interocular);
+
+        let origin = &camera.pos_at(time) + &camera.rot.apply_dir3(&local_offset).as_vec3();
+        let dir = camera.rot.apply_dir3(&local_dir).as_vec3();
+        let mut ray = Ray::new(origin, dir);
+        ray.time = time;
+        Some(ray)
+    }
+}
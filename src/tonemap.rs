@@ -0,0 +1,114 @@
+use crate::vector::{Mat4, Vec3};
+
+//maps scene linear colors to display linear colors in 0 to 1
+#[derive(Clone)]
+pub enum ToneMap {
+    Clamp,
+    Reinhard { white: f64 }, //extended reinhard on luminance, white being the value mapped to 1
+    Filmic,                  //John Hable's curve
+    Aces,                    //Stephen Hill's fit of the ACES reference and sRGB output transforms
+    Agx,                     //AgX base look, with the usual polynomial fit of its sigmoid
+}
+
+const ACES_INPUT: [[f64; 3]; 3] = [
+    [0.59719, 0.35458, 0.04823],
+    [0.07600, 0.90834, 0.01566],
+    [0.02840, 0.13383, 0.83777],
+];
+const ACES_OUTPUT: [[f64; 3]; 3] = [
+    [1.60475, -0.53108, -0.07367],
+    [-0.10208, 1.10813, -0.00605],
+    [-0.00327, -0.07276, 1.07602],
+];
+const AGX_INSET: [[f64; 3]; 3] = [
+    [0.842479062253094, 0.0784335999999992, 0.0792237451477643],
+    [0.0423282422610123, 0.878468636469772, 0.0791661274605434],
+    [0.0423756549057051, 0.0784336, 0.879142973793104],
+];
+const AGX_OUTSET: [[f64; 3]; 3] = [
+    [1.19687900512017, -0.0980208811401368, -0.0990297440797205],
+    [-0.0528968517574562, 1.15190312990417, -0.0989611768448433],
+    [-0.0529716355144438, -0.0980434501171241, 1.15107367264116],
+];
+const AGX_MIN_EV: f64 = -12.47393;
+const AGX_MAX_EV: f64 = 4.026069;
+
+pub fn luminance(color: &Vec3) -> f64 {
+    0.2126 * color.x + 0.7152 * color.y + 0.0722 * color.z
+}
+
+//the sRGB transfer function, from display linear to encoded values
+pub fn srgb_oetf(x: f64) -> f64 {
+    let x = x.clamp(0.0, 1.0);
+    if x <= 0.0031308 {
+        12.92 * x
+    } else {
+        1.055 * x.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+fn hable(x: f64) -> f64 {
+    let (a, b, c, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+    ((x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f)) - e / f
+}
+
+fn aces_rrt_odt(x: f64) -> f64 {
+    (x * (x + 0.0245786) - 0.000090537) / (x * (0.983729 * x + 0.4329510) + 0.238081)
+}
+
+fn agx_contrast(x: f64) -> f64 {
+    let x2 = x * x;
+    let x4 = x2 * x2;
+    15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x
+        - 0.00232
+}
+
+impl ToneMap {
+    pub fn from_name(name: &str, white: f64) -> Option<ToneMap> {
+        match name {
+            "clamp" | "none" => Some(ToneMap::Clamp),
+            "reinhard" => Some(ToneMap::Reinhard { white }),
+            "filmic" | "hable" => Some(ToneMap::Filmic),
+            "aces" => Some(ToneMap::Aces),
+            "agx" => Some(ToneMap::Agx),
+            _ => None,
+        }
+    }
+
+    pub fn apply(&self, color: &Vec3) -> Vec3 {
+        let color = color.apply_to_each(|c| c.max(0.0));
+        let mapped = match self {
+            ToneMap::Clamp => color,
+            ToneMap::Reinhard { white } => {
+                let l = luminance(&color);
+                if l <= 0.0 {
+                    Vec3::ZERO
+                } else {
+                    let l_out = l * (1.0 + l / (white * white)) / (1.0 + l);
+                    color.scale(l_out / l)
+                }
+            }
+            ToneMap::Filmic => {
+                //exposure bias of 2 and linear white at 11.2, as in the original
+                let white_scale = 1.0 / hable(11.2);
+                color.apply_to_each(|c| hable(2.0 * c) * white_scale)
+            }
+            ToneMap::Aces => {
+                let color = Mat4::from_rows3(ACES_INPUT).apply_dir3(&color).as_vec3();
+                let color = color.apply_to_each(aces_rrt_odt);
+                Mat4::from_rows3(ACES_OUTPUT).apply_dir3(&color).as_vec3()
+            }
+            ToneMap::Agx => {
+                let color = Mat4::from_rows3(AGX_INSET).apply_dir3(&color).as_vec3();
+                let color = color.apply_to_each(|c| {
+                    let ev = c.max(1e-10).log2().clamp(AGX_MIN_EV, AGX_MAX_EV);
+                    agx_contrast((ev - AGX_MIN_EV) / (AGX_MAX_EV - AGX_MIN_EV))
+                });
+                //back to linear, the sigmoid being fitted for a 2.2 display
+                let color = Mat4::from_rows3(AGX_OUTSET).apply_dir3(&color).as_vec3();
+                color.apply_to_each(|c| c.max(0.0).powf(2.2))
+            }
+        };
+        mapped.apply_to_each(|c| c.clamp(0.0, 1.0))
+    }
+}
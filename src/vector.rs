@@ -3,7 +3,7 @@ use std::{
     ops::{Add, Mul},
 };
 
-use rand::{thread_rng, Rng};
+use crate::sampler::Sampler;
 
 #[derive(Debug)]
 pub struct Vec3 {
@@ -72,19 +72,19 @@ impl Vec3 {
         self.scale(1.0 / self.norm())
     }
 
-    pub fn random_vector_in_unit_cube() -> Vec3 {
+    pub fn random_vector_in_unit_cube(sampler: &mut Sampler) -> Vec3 {
         Vec3 {
-            x: thread_rng().gen(),
-            y: thread_rng().gen(),
-            z: thread_rng().gen(),
+            x: sampler.get_1d(),
+            y: sampler.get_1d(),
+            z: sampler.get_1d(),
         }
         .affine(0.0, 1.0, -1.0, 1.0)
     }
 
-    pub fn random_unit_vector() -> Vec3 {
+    pub fn random_unit_vector(sampler: &mut Sampler) -> Vec3 {
         let mut vec;
         loop {
-            vec = Self::random_vector_in_unit_cube();
+            vec = Self::random_vector_in_unit_cube(sampler);
             if vec.norm() < 1.0 {
                 break;
             }
@@ -93,17 +93,17 @@ impl Vec3 {
         vec.normalized()
     }
 
-    pub fn random_vector_in_hemisphere(normal: &Vec3) -> Vec3 {
+    pub fn random_vector_in_hemisphere(normal: &Vec3, sampler: &mut Sampler) -> Vec3 {
         //algorithm : we take a random vector, and if its not in the hemisphere
         //(i.e. normal.dot(vector) <= 0) we take it's opposite.
 
-        let vec = Self::random_unit_vector();
+        let vec = Self::random_unit_vector(sampler);
         vec.scale(normal.dot(&vec).signum())
     }
 
-    pub fn random_vector_hemisphere_wrong(normal: &Self) -> Self {
-        let phi: f64 = 2.0 * PI * thread_rng().gen::<f64>();
-        let theta: f64 = 1.0 * PI * thread_rng().gen::<f64>();
+    pub fn random_vector_hemisphere_wrong(normal: &Self, u: (f64, f64)) -> Self {
+        let phi: f64 = 2.0 * PI * u.0;
+        let theta: f64 = 1.0 * PI * u.1;
 
         let vec = Vec3 {
             x: phi.cos() * theta.sin(),
@@ -113,15 +113,39 @@ impl Vec3 {
         vec.scale(normal.dot(&vec).signum())
     }
 
-    pub fn cosine_weighted_hemisphere(normal: &Self) -> Self {
-        let phi: f64 = 2.0 * PI * thread_rng().gen::<f64>();
-        let theta: f64 = thread_rng().gen::<f64>().sqrt().acos();
+    pub fn cosine_weighted_hemisphere(normal: &Self, u: (f64, f64)) -> Self {
+        let phi: f64 = 2.0 * PI * u.0;
+        let theta: f64 = u.1.sqrt().acos();
         let vec = Vec3 {
             x: phi.cos() * theta.sin(),
             y: phi.sin() * theta.sin(),
             z: theta.cos(),
         };
-        vec.rotate_to_face(&normal)
+        vec.rotate_to_face(normal)
+    }
+
+    //uniformly distributed within the cone of the given cosine around axis. It builds its own
+    //frame, as rotate_to_face snaps axes within 0.001 of z, which is wider than the sun
+    pub fn uniform_cone(axis: &Self, cos_radius: f64, u: (f64, f64)) -> Self {
+        let cos = 1.0 - u.0 * (1.0 - cos_radius);
+        let sin = (1.0 - cos * cos).max(0.0).sqrt();
+        let phi = 2.0 * PI * u.1;
+        let other = if axis.x.abs() > 0.9 {
+            Vec3 {
+                x: 0.0,
+                y: 1.0,
+                z: 0.0,
+            }
+        } else {
+            Vec3 {
+                x: 1.0,
+                y: 0.0,
+                z: 0.0,
+            }
+        };
+        let tangent = other.cross(axis).normalized();
+        let bitangent = axis.cross(&tangent);
+        &(&tangent.scale(sin * phi.cos()) + &bitangent.scale(sin * phi.sin())) + &axis.scale(cos)
     }
 
     pub fn rotate_to_face(&self, normal: &Vec3) -> Self {
@@ -250,13 +274,9 @@ pub struct Mat4 {
 impl Mat4 {
     pub fn product(&self, other: &Mat4) -> Mat4 {
         let mut res = [[0.0; 4]; 4];
-        for i in 0..4 {
-            for j in 0..4 {
-                let mut sum = 0.0;
-                for k in 0..4 {
-                    sum += self.elems[i][k] * other.elems[k][j];
-                }
-                res[i][j] = sum;
+        for (i, row) in res.iter_mut().enumerate() {
+            for (j, cell) in row.iter_mut().enumerate() {
+                *cell = (0..4).map(|k| self.elems[i][k] * other.elems[k][j]).sum();
             }
         }
         Mat4 { elems: res }
@@ -265,12 +285,8 @@ impl Mat4 {
     pub fn apply(&self, other: &Vec4) -> Vec4 {
         let other_ = [other.x, other.y, other.z, other.w];
         let mut res = [0.0; 4];
-        for i in 0..4 {
-            let mut sum = 0.0;
-            for j in 0..4 {
-                sum += self.elems[i][j] * other_[j];
-            }
-            res[i] = sum;
+        for (cell, row) in res.iter_mut().zip(self.elems.iter()) {
+            *cell = row.iter().zip(other_.iter()).map(|(a, b)| a * b).sum();
         }
 
         Vec4 {
@@ -301,7 +317,7 @@ impl Mat4 {
         self.apply(&v4)
     }
 
-    pub fn fromColumns(col_1: &Vec3, col_2: &Vec3, col_3: &Vec3) -> Mat4 {
+    pub fn from_columns(col_1: &Vec3, col_2: &Vec3, col_3: &Vec3) -> Mat4 {
         let elems = [
             [col_1.x, col_2.x, col_3.x, 0.0],
             [col_1.y, col_2.y, col_3.y, 0.0],
@@ -312,6 +328,42 @@ impl Mat4 {
         Mat4 { elems }
     }
 
+    //a linear transform given by the rows of its 3x3 matrix
+    pub fn from_rows3(rows: [[f64; 3]; 3]) -> Mat4 {
+        let mut elems = [[0.0; 4]; 4];
+        for (row, src) in elems.iter_mut().zip(rows.iter()) {
+            row[..3].copy_from_slice(src);
+        }
+        elems[3][3] = 1.0;
+        Mat4 { elems }
+    }
+
+    pub fn transposed(&self) -> Mat4 {
+        let mut elems = [[0.0; 4]; 4];
+        for (i, row) in elems.iter_mut().enumerate() {
+            for (j, cell) in row.iter_mut().enumerate() {
+                *cell = self.elems[j][i];
+            }
+        }
+        Mat4 { elems }
+    }
+
+    //inverse of a rotation followed by a translation
+    pub fn rigid_inverse(&self) -> Mat4 {
+        let mut inverse = self.transposed();
+        inverse.elems[3] = [0.0, 0.0, 0.0, 1.0];
+        let translation = Vec3 {
+            x: self.elems[0][3],
+            y: self.elems[1][3],
+            z: self.elems[2][3],
+        };
+        let inv_translation = inverse.apply_dir3(&translation).as_vec3().scale(-1.0);
+        inverse.elems[0][3] = inv_translation.x;
+        inverse.elems[1][3] = inv_translation.y;
+        inverse.elems[2][3] = inv_translation.z;
+        inverse
+    }
+
     pub fn look_at(v1: &Vec3, v2: &Vec3) -> Mat4 {
         Self::look_in_dir(&v2.minus(v1).normalized())
     }
@@ -331,6 +383,6 @@ impl Mat4 {
         .normalized();
         let col_2 = col_3.cross(&col_1);
 
-        Mat4::fromColumns(&col_1, &col_2, &col_3)
+        Mat4::from_columns(&col_1, &col_2, &col_3)
     }
 }